@@ -0,0 +1,365 @@
+use std::cmp::Ordering;
+use std::hash::Hasher;
+
+use fnv::{FnvHashMap, FnvHasher};
+use ndarray::{Array1, Array2};
+use ndarray_linalg::Solve;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+
+use crate::IncrementalDecrementalModel;
+
+/// Matrix factorization for implicit feedback via alternating least squares, see Hu et al.,
+/// "Collaborative filtering for implicit feedback datasets". Every interaction has a preference
+/// of one and a confidence of `1 + alpha`, all other user-item pairs have a preference of zero
+/// and a confidence of one.
+///
+/// Forgetting a user drops their factor vector. Afterwards, we alternate between recomputing the
+/// factors of the items they interacted with and the factors of the other users of these items,
+/// for at most `max_forget_sweeps` sweeps. This only approximates a full retrain, as the factors
+/// of all users contribute to the Gram matrix used for every item, `residual_against_retrain`
+/// measures how far off we are.
+#[derive(Debug)]
+pub struct ImplicitALS {
+    num_factors: usize,
+    lambda: f64,
+    alpha: f64,
+    num_iterations: usize,
+    max_forget_sweeps: usize,
+    user_items: FnvHashMap<u32, Vec<u32>>,
+    item_users: Vec<Vec<u32>>,
+    user_factors: FnvHashMap<u32, Array1<f64>>,
+    item_factors: Vec<Array1<f64>>,
+}
+
+impl ImplicitALS {
+
+    pub fn new(num_factors: usize, lambda: f64, alpha: f64) -> Self {
+        ImplicitALS::with_sweeps(num_factors, lambda, alpha, 10, 2)
+    }
+
+    /// `num_iterations` full ALS iterations are run on every `partial_fit`, forgetting runs at
+    /// most `max_forget_sweeps` sweeps over the affected items and users
+    pub fn with_sweeps(
+        num_factors: usize,
+        lambda: f64,
+        alpha: f64,
+        num_iterations: usize,
+        max_forget_sweeps: usize)
+    -> Self {
+        ImplicitALS {
+            num_factors,
+            lambda,
+            alpha,
+            num_iterations,
+            max_forget_sweeps,
+            user_items: FnvHashMap::default(),
+            item_users: Vec::new(),
+            user_factors: FnvHashMap::default(),
+            item_factors: Vec::new(),
+        }
+    }
+
+    /// Forgets all interactions of a user and drops their factor vector
+    pub fn forget_user(&mut self, user: u32) {
+        if let Some(items) = self.user_items.get(&user) {
+            let items = items.clone();
+            self.forget(&(user, items));
+        }
+    }
+
+    /// Predicted preference of a user for an item, zero for unknown users and items
+    pub fn score(&self, user: u32, item: u32) -> f64 {
+        match (self.user_factors.get(&user), self.item_factors.get(item as usize)) {
+            (Some(user_factor), Some(item_factor)) => user_factor.dot(item_factor),
+            _ => 0.0,
+        }
+    }
+
+    /// Recommends the `n` highest scoring items which the user has not interacted with yet, ties
+    /// are broken in favor of the smaller item id
+    pub fn recommend(&self, user: u32, n: usize) -> Vec<(u32, f64)> {
+        let seen_items = match self.user_items.get(&user) {
+            Some(items) => items,
+            None => return Vec::new(),
+        };
+
+        let mut recommendations: Vec<(u32, f64)> = (0..self.item_factors.len() as u32)
+            .filter(|item| seen_items.binary_search(item).is_err())
+            .map(|item| (item, self.score(user, item)))
+            .collect();
+
+        recommendations.sort_by(|(item_a, score_a), (item_b, score_b)| {
+            score_b.partial_cmp(score_a)
+                .unwrap_or(Ordering::Equal)
+                .then(item_a.cmp(item_b))
+        });
+        recommendations.truncate(n);
+        recommendations
+    }
+
+    /// Retrains a model with the same parameters from scratch on the remaining interactions and
+    /// returns the root mean squared difference of the predicted scores for all user-item pairs
+    pub fn residual_against_retrain(&self) -> f64 {
+        let mut users: Vec<u32> = self.user_items.keys().cloned().collect();
+        users.sort();
+
+        let interactions: Vec<(u32, Vec<u32>)> = users.iter()
+            .map(|user| (*user, self.user_items[user].clone()))
+            .collect();
+
+        let mut retrained = ImplicitALS::with_sweeps(self.num_factors, self.lambda, self.alpha,
+            self.num_iterations, self.max_forget_sweeps);
+        retrained.partial_fit(&interactions);
+
+        let mut squared_error = 0.0;
+        for user in users.iter() {
+            for item in 0..self.item_factors.len() as u32 {
+                let difference = self.score(*user, item) - retrained.score(*user, item);
+                squared_error += difference * difference;
+            }
+        }
+
+        let num_pairs = users.len() * self.item_factors.len();
+        if num_pairs == 0 { 0.0 } else { (squared_error / num_pairs as f64).sqrt() }
+    }
+
+    fn ensure_item(&mut self, item: u32) {
+        while self.item_factors.len() <= item as usize {
+            let new_item = self.item_factors.len() as u32;
+            self.item_factors.push(initial_factor(self.num_factors, ITEM, new_item));
+            self.item_users.push(Vec::new());
+        }
+    }
+
+    fn sweep(&mut self) {
+        let mut users: Vec<u32> = self.user_items.keys().cloned().collect();
+        users.sort();
+        self.update_users(&users);
+
+        let items: Vec<u32> = (0..self.item_factors.len() as u32).collect();
+        self.update_items(&items);
+    }
+
+    /// Solves for the factors of the given users while keeping the item factors fixed
+    fn update_users(&mut self, users: &[u32]) {
+        let gram = gram_matrix(self.item_factors.iter(), self.num_factors);
+
+        for user in users.iter() {
+            let item_factors = &self.item_factors;
+            let interacted_factors = self.user_items[user].iter()
+                .map(|item| &item_factors[*item as usize]);
+
+            let factor = least_squares(&gram, interacted_factors, self.lambda, self.alpha);
+            self.user_factors.insert(*user, factor);
+        }
+    }
+
+    /// Solves for the factors of the given items while keeping the user factors fixed, items
+    /// without any interactions end up with a zero factor
+    fn update_items(&mut self, items: &[u32]) {
+        let gram = gram_matrix(self.user_factors.values(), self.num_factors);
+
+        for item in items.iter() {
+            let user_factors = &self.user_factors;
+            let interacted_factors = self.item_users[*item as usize].iter()
+                .map(|user| &user_factors[user]);
+
+            self.item_factors[*item as usize] =
+                least_squares(&gram, interacted_factors, self.lambda, self.alpha);
+        }
+    }
+}
+
+const USER: u8 = 0;
+const ITEM: u8 = 1;
+
+/// Small random initial factor, which only depends on the id, so that a retrained model starts
+/// from the same factors
+fn initial_factor(num_factors: usize, kind: u8, id: u32) -> Array1<f64> {
+    let mut hasher = FnvHasher::default();
+    hasher.write_u8(kind);
+    hasher.write_u32(id);
+    let mut rng = StdRng::seed_from_u64(hasher.finish());
+
+    Array1::from_shape_fn(num_factors, |_| rng.gen_range(-0.01, 0.01))
+}
+
+fn add_outer_product(matrix: &mut Array2<f64>, vector: &Array1<f64>, weight: f64) {
+    for row in 0..vector.len() {
+        for column in 0..vector.len() {
+            matrix[[row, column]] += weight * vector[row] * vector[column];
+        }
+    }
+}
+
+fn gram_matrix<'a>(
+    factors: impl Iterator<Item=&'a Array1<f64>>,
+    num_factors: usize
+) -> Array2<f64> {
+    let mut gram = Array2::zeros((num_factors, num_factors));
+    for factor in factors {
+        add_outer_product(&mut gram, factor, 1.0);
+    }
+    gram
+}
+
+/// Solves `(G + alpha * sum(y y') + lambda * I) x = (1 + alpha) * sum(y)` for the factors `y` of
+/// the interactions, where `G` is the Gram matrix of all factors on the other side. This only
+/// costs time proportional to the number of interactions, as all non-interactions have a
+/// confidence of one and are covered by `G`.
+fn least_squares<'a>(
+    gram: &Array2<f64>,
+    interacted_factors: impl Iterator<Item=&'a Array1<f64>>,
+    lambda: f64,
+    alpha: f64
+) -> Array1<f64> {
+    let mut a = gram.clone();
+    let mut b = Array1::zeros(gram.rows());
+
+    for factor in interacted_factors {
+        add_outer_product(&mut a, factor, alpha);
+        b.scaled_add(1.0 + alpha, factor);
+    }
+    for index in 0..a.rows() {
+        a[[index, index]] += lambda;
+    }
+
+    a.solve_into(b).expect("Unable to solve the least squares problem")
+}
+
+impl IncrementalDecrementalModel<(u32, Vec<u32>), (u32, u32), f64> for ImplicitALS {
+
+    /// Adds the interactions of users with items and runs `num_iterations` ALS iterations,
+    /// starting from the current factors
+    fn partial_fit(&mut self, interactions: &[(u32, Vec<u32>)]) {
+        for (user, items) in interactions.iter() {
+            for item in items.iter() {
+                self.ensure_item(*item);
+
+                let history = self.user_items.entry(*user).or_default();
+                if let Err(position) = history.binary_search(item) {
+                    history.insert(position, *item);
+
+                    let users = &mut self.item_users[*item as usize];
+                    let position = users.binary_search(user).unwrap_err();
+                    users.insert(position, *user);
+                }
+            }
+
+            if self.user_items.contains_key(user) && !self.user_factors.contains_key(user) {
+                self.user_factors.insert(*user, initial_factor(self.num_factors, USER, *user));
+            }
+        }
+
+        for _ in 0..self.num_iterations {
+            self.sweep();
+        }
+    }
+
+    /// Forgets the given interactions of a user, the factor vector of a user without any
+    /// remaining interactions is dropped
+    fn forget(&mut self, interactions: &(u32, Vec<u32>)) {
+        let (user, items) = interactions;
+
+        let history = self.user_items.get_mut(user).expect("Unknown user");
+        let mut affected_items = Vec::with_capacity(items.len());
+        for item in items.iter() {
+            if let Ok(position) = history.binary_search(item) {
+                history.remove(position);
+                affected_items.push(*item);
+            }
+        }
+
+        if history.is_empty() {
+            self.user_items.remove(user);
+            self.user_factors.remove(user);
+        }
+
+        for item in affected_items.iter() {
+            let users = &mut self.item_users[*item as usize];
+            let position = users.binary_search(user).unwrap();
+            users.remove(position);
+        }
+
+        affected_items.sort();
+        affected_items.dedup();
+
+        let mut affected_users: Vec<u32> = affected_items.iter()
+            .flat_map(|item| self.item_users[*item as usize].iter().cloned())
+            .collect();
+        if self.user_items.contains_key(user) {
+            affected_users.push(*user);
+        }
+        affected_users.sort();
+        affected_users.dedup();
+
+        for _ in 0..self.max_forget_sweeps {
+            self.update_items(&affected_items);
+            self.update_users(&affected_users);
+        }
+    }
+
+    fn predict(&self, user_and_item: &(u32, u32)) -> f64 {
+        let (user, item) = user_and_item;
+        self.score(*user, *item)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::IncrementalDecrementalModel;
+    use crate::als::ImplicitALS;
+
+    fn two_clusters() -> Vec<(u32, Vec<u32>)> {
+        (0..10)
+            .map(|user| {
+                let items = if user < 5 { vec![0, 1, 2] } else { vec![3, 4, 5] };
+                (user, items)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn recommends_items_of_similar_users() {
+        let mut als = ImplicitALS::new(2, 0.1, 10.0);
+        als.partial_fit(&two_clusters());
+        als.partial_fit(&[(10, vec![0, 1]), (11, vec![4])]);
+
+        assert_eq!(als.recommend(10, 1)[0].0, 2);
+        assert!(als.predict(&(11, 3)) > als.predict(&(11, 0)));
+        assert_eq!(als.predict(&(12, 0)), 0.0);
+    }
+
+    #[test]
+    fn forgetting_a_user_drops_their_factor() {
+        let mut interactions = two_clusters();
+        interactions.push((10, vec![0, 1, 2, 3, 4, 5]));
+
+        let mut als = ImplicitALS::new(2, 0.1, 10.0);
+        als.partial_fit(&interactions);
+
+        als.forget_user(10);
+
+        assert!(!als.user_factors.contains_key(&10));
+        assert!(als.item_users.iter().all(|users| !users.contains(&10)));
+        assert_eq!(als.predict(&(10, 0)), 0.0);
+
+        // Forgetting the user who connected both clusters makes them independent again
+        assert!(als.residual_against_retrain() < 0.05);
+    }
+
+    #[test]
+    fn forgetting_single_interactions() {
+        let mut als = ImplicitALS::new(2, 0.1, 10.0);
+        als.partial_fit(&two_clusters());
+
+        als.forget(&(0, vec![2]));
+        assert_eq!(als.user_items[&0], vec![0, 1]);
+        assert!(!als.item_users[2].contains(&0));
+        assert!(als.user_factors.contains_key(&0));
+
+        assert!(als.residual_against_retrain() < 0.05);
+    }
+}
@@ -0,0 +1,60 @@
+extern crate amnesia;
+extern crate rand;
+
+use std::time::Instant;
+
+use amnesia::IncrementalDecrementalModel;
+use amnesia::als::ImplicitALS;
+use amnesia::differential::io_utils::read_interactions;
+
+use rand::Rng;
+
+fn main() {
+    let num_users_to_forget = 20;
+
+    // Retraining from scratch after every forgotten user is expensive, so we only compare against
+    // a full retrain on request
+    let report_residuals = std::env::args().any(|arg| arg == "--report-residuals");
+
+    run_experiment("datasets/movielens1m.tsv", 6040, num_users_to_forget, report_residuals);
+    run_experiment("datasets/jester.tsv", 50692, num_users_to_forget, report_residuals);
+    run_experiment("datasets/ciaodvd.tsv", 21019, num_users_to_forget, report_residuals);
+}
+
+fn run_experiment(
+    dataset_file: &'static str,
+    num_users: usize,
+    num_users_to_forget: usize,
+    report_residuals: bool
+) {
+
+    let interactions = read_interactions(dataset_file, num_users);
+
+    let mut als = ImplicitALS::new(10, 0.1, 40.0);
+
+    println!("Training full model");
+    let start = Instant::now();
+    als.partial_fit(&interactions);
+    println!("Training took {} ms", start.elapsed().as_millis());
+
+    for _ in 0 .. num_users_to_forget {
+        let mut rng = rand::thread_rng();
+        let user = rng.gen_range(0, num_users);
+
+        let start = Instant::now();
+        als.forget_user(user as u32);
+        let forgetting_duration = start.elapsed();
+
+        if report_residuals {
+            let start = Instant::now();
+            let residual = als.residual_against_retrain();
+            let retraining_duration = start.elapsed();
+
+            println!("{},{},{},{},{},{}", dataset_file, user, interactions[user].1.len(),
+                forgetting_duration.as_millis(), retraining_duration.as_millis(), residual);
+        } else {
+            println!("{},{},{},{}", dataset_file, user, interactions[user].1.len(),
+                forgetting_duration.as_millis());
+        }
+    }
+}
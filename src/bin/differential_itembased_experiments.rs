@@ -3,6 +3,8 @@ extern crate timely;
 extern crate differential_dataflow;
 
 use differential_dataflow::input::InputSession;
+use amnesia::similarity::Similarity;
+use amnesia::user_cap::{UserCap, CapPolicy};
 use std::time::Instant;
 use rand::seq::SliceRandom;
 #[allow(deprecated)]
@@ -28,7 +30,11 @@ fn run_experiment(
 
         let mut interactions_input = InputSession::new();
 
-        let probe = amnesia::differential::itembased::itembased_cf(worker, &mut interactions_input);
+        // Drop users with 500 or more interactions, itembased_experiments uses the same cap
+        let user_cap = UserCap::new(499, CapPolicy::Drop);
+
+        let probe = amnesia::differential::itembased::itembased_cf(
+            worker, &mut interactions_input, Similarity::Jaccard, user_cap, 0.05, 10);
 
         let mut interactions =
             amnesia::differential::io_utils::read_interactions(dataset_file, num_users);
@@ -6,6 +6,8 @@ use std::time::Instant;
 
 use amnesia::IncrementalDecrementalModel;
 use amnesia::itembased::ItembasedCF;
+use amnesia::similarity::Similarity;
+use amnesia::user_cap::{UserCap, CapPolicy};
 
 use rand::Rng;
 
@@ -30,7 +32,8 @@ fn run_experiment(
         .from_path(dataset_file)
         .expect("Unable to read input file");
 
-    let mut interactions: Vec<Vec<u32>> = (0 .. num_users).map(|_| Vec::new()).collect();
+    let mut interactions: Vec<(u32, Vec<u32>)> = (0 .. num_users)
+        .map(|index| (index as u32, Vec::new())).collect();
 
     reader.deserialize()
         .for_each(|result| {
@@ -39,17 +42,23 @@ fn run_experiment(
                 let user_idx = user - 1;
                 let item_idx = item - 1;
 
-                if interactions[user_idx as usize].len() < 500 {
-                    interactions[user_idx as usize].push(item_idx);
-                }
+                // The cap on the number of interactions per user is applied by the model
+                interactions[user_idx as usize].1.push(item_idx);
             }
         });
 
-    let mut itembased_cf = ItembasedCF::new(num_items, 10);
+    // Same cap as in the differential experiments, so that both compute the same model
+    let user_cap = UserCap::new(499, CapPolicy::Drop);
+
+    let mut itembased_cf = ItembasedCF::with_user_cap(num_items, 10, Similarity::Jaccard, user_cap);
+
+    let num_threads = std::thread::available_parallelism()
+        .map(|parallelism| parallelism.get())
+        .unwrap_or(1);
 
     println!("Training full model");
     let start = Instant::now();
-    itembased_cf.partial_fit(&interactions);
+    itembased_cf.partial_fit_parallel(&interactions, num_threads);
     println!("Training took {} ms", start.elapsed().as_millis());
 
     let mut interactions_without_users = interactions.clone();
@@ -64,15 +73,16 @@ fn run_experiment(
         itembased_cf.forget(user_interactions);
         let forgetting_duration = start.elapsed();
 
-        interactions_without_users[user].clear();
+        interactions_without_users[user].1.clear();
 
-        let mut itembased_cf_without_user = ItembasedCF::new(num_items, 10);
+        let mut itembased_cf_without_user =
+            ItembasedCF::with_user_cap(num_items, 10, Similarity::Jaccard, user_cap);
 
         let start = Instant::now();
-        itembased_cf_without_user.partial_fit(&interactions_without_users);
+        itembased_cf_without_user.partial_fit_parallel(&interactions_without_users, num_threads);
         let retraining_duration = start.elapsed();
 
-        println!("{},{},{},{},{}", dataset_file, user, user_interactions.len(),
+        println!("{},{},{},{},{}", dataset_file, user, user_interactions.1.len(),
             forgetting_duration.as_millis(), retraining_duration.as_millis());
     }
 
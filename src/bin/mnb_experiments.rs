@@ -3,7 +3,7 @@ extern crate rand;
 
 use std::time::Instant;
 use rand::Rng;
-use amnesia::mnb::MultinomialNaiveBayes;
+use amnesia::mnb::{MultinomialNaiveBayes, CountsLayout};
 use amnesia::IncrementalDecrementalModel;
 
 fn main() {
@@ -11,6 +11,9 @@ fn main() {
     run_experiment("datasets/mushrooms.libsvm", 112, num_examples_to_forget, 2, true);
     run_experiment("datasets/phishing.libsvm", 68, num_examples_to_forget, 2, false);
     run_experiment("datasets/covtype.libsvm", 54, num_examples_to_forget, 7, true);
+
+    run_layout_benchmark("datasets/covtype.libsvm", 54, 7, true);
+    run_layout_benchmark("datasets/phishing.libsvm", 68, 2, false);
 }
 
 fn run_experiment(
@@ -51,4 +54,31 @@ fn run_experiment(
             retraining_duration.as_micros());
     }
 
-}
\ No newline at end of file
+}
+
+fn run_layout_benchmark(
+    dataset_file: &str,
+    num_features: u32,
+    num_labels: u8,
+    adjust_labels: bool)
+{
+    let examples = amnesia::io_utils::read_libsvm_file_for_mnb(&dataset_file, adjust_labels);
+
+    for (layout_name, layout) in &[("dense", CountsLayout::Dense), ("sparse", CountsLayout::Sparse)] {
+
+        let mut mnb = MultinomialNaiveBayes::with_layout(num_labels, num_features, *layout);
+
+        let start = Instant::now();
+        mnb.partial_fit(&examples);
+        let training_duration = start.elapsed();
+
+        let start = Instant::now();
+        let num_correct = examples.iter()
+            .filter(|(features, label)| mnb.predict(features) == *label)
+            .count();
+        let prediction_duration = start.elapsed();
+
+        println!("layout,{},{},{},{},{}", dataset_file, layout_name,
+            training_duration.as_micros(), prediction_duration.as_micros(), num_correct);
+    }
+}
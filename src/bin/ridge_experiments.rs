@@ -1,16 +1,18 @@
 extern crate amnesia;
-extern crate rgsl;
+extern crate ndarray;
 extern crate rand;
 
 use std::io::BufReader;
 use std::io::BufRead;
 use std::fs::File;
+use std::fmt;
 use std::time::Instant;
 
-use rgsl::{MatrixF64, VectorF64};
+use ndarray::{Array1, Array2, Axis};
 
 use amnesia::IncrementalDecrementalModel;
-use amnesia::ridge::{RidgeRegression, Example};
+use amnesia::kernel_ridge::{Kernel, KernelRidgeRegression};
+use amnesia::ridge::{RidgeRegression, InverseRidgeRegression, Example, Preprocessing};
 
 use rand::Rng;
 
@@ -42,62 +44,18 @@ fn read_libsvm_file(dataset_file: &str, num_features: usize) -> Vec<Example> {
             }
         }
 
-        let feature_vector = VectorF64::from_slice(&features).unwrap();
-
-        examples.push(Example::new(feature_vector, target));
+        examples.push(Example::new(Array1::from_vec(features), target));
     }
 
     examples
 }
 
-fn read_libsvm_file_matrix_vector(
-    dataset_file: &str,
-    num_examples: usize,
-    num_features: usize
-) -> (MatrixF64, VectorF64) {
-
-    let mut examples = MatrixF64::new(num_examples, num_features).unwrap();
-    let mut targets = VectorF64::new(num_examples).unwrap();
-
-    let handle = File::open(dataset_file).expect("Unable to read file!");
-
-    let file = BufReader::new(&handle);
-    for (row, line) in file.lines().enumerate() {
-        let l = line.unwrap();
-
-        let tokens = l.split(' ');
-        for (index, t) in tokens.enumerate() {
-            if index == 0 {
-                let target_value = t.parse::<f64>().expect("Unable to parse target!");
-                targets.set(index, target_value);
-            } else {
-                if !t.is_empty() {
-                    let tt: Vec<_> = t.split(':').collect();
-                    let offset = tt[0].parse::<usize>().expect("Unable to parse offset!") - 1;
-                    let value = tt[1].parse::<f64>().expect("Unable to parse value!");
-
-                    examples.set(row, offset, value);
-                }
-            }
-        }
-    }
-
-    (examples, targets)
-}
-
-fn examples_to_matrix(examples: &[Example], num_features: usize) -> (MatrixF64, VectorF64) {
-
-    let num_examples = examples.len();
+fn examples_to_matrix(examples: &[Example], num_features: usize) -> (Array2<f64>, Array2<f64>) {
 
-    let mut x = MatrixF64::new(num_examples, num_features).unwrap();
-    let mut y = VectorF64::new(num_examples).unwrap();
-
-    for (index, example) in examples.iter().enumerate() {
-        y.set(index, example.target);
-        for offset in 0..num_features {
-            x.set(index, offset, example.features.get(offset));
-        }
-    }
+    let x = Array2::from_shape_fn((examples.len(), num_features), |(index, offset)| {
+        examples[index].features[offset]
+    });
+    let y = Array2::from_shape_fn((examples.len(), 1), |(index, _)| examples[index].targets[0]);
 
     (x, y)
 }
@@ -107,40 +65,159 @@ fn main() {
 
     let num_examples_to_forget = 20;
 
-    run_experiment("datasets/housing_scale.libsvm", 506, 13, num_examples_to_forget);
-    run_experiment("datasets/cadata.libsvm",  20_640, 8, num_examples_to_forget);
-    run_experiment("datasets/YearPredictionMSD.libsvm",  463_715, 90, num_examples_to_forget);
+    run_experiment("datasets/housing_scale.libsvm", 13, num_examples_to_forget);
+    run_experiment("datasets/cadata.libsvm", 8, num_examples_to_forget);
+    run_experiment("datasets/YearPredictionMSD.libsvm", 90, num_examples_to_forget);
 }
 
+/// The kernel matrix grows quadratically, so we only run kernel ridge on the smaller datasets
+const MAX_KERNEL_EXAMPLES: usize = 5_000;
+/// Number of examples on which we compare the predictions of the models
+const NUM_EVALUATION_EXAMPLES: usize = 1_000;
+
 fn run_experiment(
     dataset_file: &str,
-    num_examples: usize,
     num_features: usize,
     num_examples_to_forget: usize)
 {
     let mut examples = read_libsvm_file(dataset_file, num_features);
 
-    let (x, y) = read_libsvm_file_matrix_vector(dataset_file, num_examples, num_features);
+    // We build the design matrix once, and select the rows of the remaining examples for
+    // retraining. The row indices are removed together with the forgotten examples.
+    let (all_x, all_y) = examples_to_matrix(&examples, num_features);
+    let mut rows: Vec<usize> = (0..examples.len()).collect();
+
+    // Without preprocessing, forgetting is a rank-one downdate of the Cholesky factor
+    let mut ridge = RidgeRegression::new(all_x.clone(), all_y.clone(), 0.001);
+
+    // The libsvm features have very different ranges, so we also report a standardized model.
+    // Its feature scales change with every example, so forgetting refactorizes in O(d³).
+    let mut standardized_ridge = RidgeRegression::with_preprocessing(
+        all_x.clone(), all_y.clone(), 0.001, Preprocessing::Standardize);
 
-    let mut ridge = RidgeRegression::new(x, y, 0.001);
+    // The inverse is maintained for the raw features, which shows its numerical limitations
+    let mut inverse_ridge = InverseRidgeRegression::new(all_x.clone(), all_y.clone(), 0.001);
+
+    let kernel = Kernel::Rbf { gamma: 1.0 / num_features as f64 };
+    let mut kernel_ridge = if examples.len() <= MAX_KERNEL_EXAMPLES {
+        let mut kernel_ridge = KernelRidgeRegression::new(kernel, 0.001, 1);
+        kernel_ridge.partial_fit(&examples);
+        Some(kernel_ridge)
+    } else {
+        None
+    };
 
     let mut rng = rand::thread_rng();
     for _ in 0 .. num_examples_to_forget {
         let index = rng.gen_range(0, examples.len());
 
         let example_to_forget = examples.remove(index);
+        rows.remove(index);
+        let evaluation_examples = &examples[..NUM_EVALUATION_EXAMPLES.min(examples.len())];
+
+        let x = all_x.select(Axis(0), &rows);
+        let y = all_y.select(Axis(0), &rows);
+
+        let (ridge_x, ridge_y) = (x.clone(), y.clone());
+        let comparison = forget_and_retrain(
+            &mut ridge,
+            &example_to_forget,
+            || RidgeRegression::new(ridge_x, ridge_y, 0.001),
+            |ridge| ridge.coefficients().1,
+            evaluation_examples);
+
+        println!("{},ridge,{}", dataset_file, comparison);
+
+        let (standardized_x, standardized_y) = (x.clone(), y.clone());
+        let comparison = forget_and_retrain(
+            &mut standardized_ridge,
+            &example_to_forget,
+            || {
+                RidgeRegression::with_preprocessing(
+                    standardized_x, standardized_y, 0.001, Preprocessing::Standardize)
+            },
+            |standardized_ridge| standardized_ridge.coefficients().1,
+            evaluation_examples);
+
+        println!("{},standardized_ridge,{}", dataset_file, comparison);
+
+        let comparison = forget_and_retrain(
+            &mut inverse_ridge,
+            &example_to_forget,
+            || InverseRidgeRegression::new(x, y, 0.001),
+            |inverse_ridge| inverse_ridge.weights().clone(),
+            evaluation_examples);
+
+        println!("{},inverse_ridge,{}", dataset_file, comparison);
+
+        if let Some(kernel_ridge) = kernel_ridge.as_mut() {
+            let comparison = forget_and_retrain(
+                kernel_ridge,
+                &example_to_forget,
+                || {
+                    let mut retrained = KernelRidgeRegression::new(kernel, 0.001, 1);
+                    retrained.partial_fit(&examples);
+                    retrained
+                },
+                |kernel_ridge| kernel_ridge.dual_weights().clone(),
+                evaluation_examples);
+
+            println!("{},kernel_ridge,{}", dataset_file, comparison);
+        }
+    }
+}
 
-        let start = Instant::now();
-        ridge.forget(&example_to_forget);
-        let forgetting_duration = start.elapsed();
-
-        let (x_for_retrain, y_for_retrain) = examples_to_matrix(&examples, num_features);
+/// Durations in microseconds and the largest absolute differences between the predictions and
+/// the weights of a model after forgetting an example and the model retrained without it
+struct Comparison {
+    forgetting_duration: u128,
+    retrain_duration: u128,
+    prediction_difference: f64,
+    weight_difference: f64,
+}
 
-        let start = Instant::now();
-        let _retrained_ridge = RidgeRegression::new(x_for_retrain, y_for_retrain, 0.001);
-        let retrain_duration = start.elapsed();
+impl fmt::Display for Comparison {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "{},{},{},{}", self.forgetting_duration, self.retrain_duration,
+            self.prediction_difference, self.weight_difference)
+    }
+}
 
-        println!("{},{},{}", dataset_file, forgetting_duration.as_micros(),
-            retrain_duration.as_micros());
+/// Forgets the example, retrains the model from scratch and compares both models
+fn forget_and_retrain<M, R, W>(
+    model: &mut M,
+    example_to_forget: &Example,
+    retrain: R,
+    weights: W,
+    evaluation_examples: &[Example])
+    -> Comparison
+    where M: IncrementalDecrementalModel<Example, Array1<f64>, Array1<f64>>,
+          R: FnOnce() -> M,
+          W: Fn(&M) -> Array2<f64>
+{
+    let start = Instant::now();
+    model.forget(example_to_forget);
+    let forgetting_duration = start.elapsed();
+
+    let start = Instant::now();
+    let retrained = retrain();
+    let retrain_duration = start.elapsed();
+
+    let prediction_difference = evaluation_examples.iter()
+        .map(|example| {
+            let prediction = model.predict(&example.features)[0];
+            (prediction - retrained.predict(&example.features)[0]).abs()
+        })
+        .fold(0.0, f64::max);
+
+    let weight_difference = weights(model).iter().zip(weights(&retrained).iter())
+        .map(|(weight, retrained_weight)| (weight - retrained_weight).abs())
+        .fold(0.0, f64::max);
+
+    Comparison {
+        forgetting_duration: forgetting_duration.as_micros(),
+        retrain_duration: retrain_duration.as_micros(),
+        prediction_difference,
+        weight_difference,
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,55 @@
+extern crate amnesia;
+extern crate rand;
+
+use std::time::Instant;
+
+use amnesia::IncrementalDecrementalModel;
+use amnesia::differential::io_utils::read_interactions;
+use amnesia::userbased::UserbasedCF;
+
+use rand::Rng;
+
+fn main() {
+    let num_users_to_forget = 20;
+
+    run_experiment("datasets/movielens1m.tsv", 6040, num_users_to_forget);
+    // Jester has very few items which almost all users interacted with, which results in a
+    // quadratic number of user pairs, so we only run on the sparser datasets
+    run_experiment("datasets/ciaodvd.tsv", 21019, num_users_to_forget);
+}
+
+fn run_experiment(dataset_file: &'static str, num_users: usize, num_users_to_forget: usize) {
+
+    let interactions = read_interactions(dataset_file, num_users);
+
+    let mut userbased_cf = UserbasedCF::new(10);
+
+    println!("Training full model");
+    let start = Instant::now();
+    userbased_cf.partial_fit(&interactions);
+    println!("Training took {} ms", start.elapsed().as_millis());
+
+    let mut interactions_without_users = interactions.clone();
+
+    for _ in 0 .. num_users_to_forget {
+        let mut rng = rand::thread_rng();
+        let user = rng.gen_range(0, num_users);
+
+        let user_interactions = &interactions[user];
+
+        let start = Instant::now();
+        userbased_cf.forget_user(user as u32);
+        let forgetting_duration = start.elapsed();
+
+        interactions_without_users[user].1.clear();
+
+        let mut userbased_cf_without_user = UserbasedCF::new(10);
+
+        let start = Instant::now();
+        userbased_cf_without_user.partial_fit(&interactions_without_users);
+        let retraining_duration = start.elapsed();
+
+        println!("{},{},{},{},{}", dataset_file, user, user_interactions.1.len(),
+            forgetting_duration.as_millis(), retraining_duration.as_millis());
+    }
+}
@@ -0,0 +1,68 @@
+extern crate amnesia;
+extern crate rand;
+
+use std::time::Instant;
+
+use amnesia::IncrementalDecrementalModel;
+use amnesia::weighted_itembased::{WeightedItembasedCF, WeightedSimilarity};
+
+use rand::Rng;
+
+fn main() {
+    let num_users_to_forget = 20;
+
+    run_experiment("datasets/movielens1m.tsv", 6040, 3706, num_users_to_forget);
+    run_experiment("datasets/ciaodvd.tsv", 21019, 71633, num_users_to_forget);
+}
+
+fn run_experiment(
+    dataset_file: &'static str,
+    num_users: usize,
+    num_items: usize,
+    num_users_to_forget: usize
+) {
+    let interactions =
+        amnesia::differential::io_utils::read_rated_interactions(dataset_file, num_users);
+
+    let ratings: Vec<(u32, u32, f64)> = interactions.iter()
+        .flat_map(|(user, user_ratings)| {
+            user_ratings.iter().map(move |(item, rating)| (*user, *item, *rating))
+        })
+        .collect();
+
+    let mut weighted_cf =
+        WeightedItembasedCF::new(num_items, 10, WeightedSimilarity::AdjustedCosine);
+
+    println!("Training full model");
+    let start = Instant::now();
+    weighted_cf.partial_fit(&ratings);
+    println!("Training took {} ms", start.elapsed().as_millis());
+
+    let mut forgotten_users = vec![false; num_users];
+
+    for _ in 0 .. num_users_to_forget {
+        let mut rng = rand::thread_rng();
+        let user = rng.gen_range(0, num_users);
+
+        let start = Instant::now();
+        weighted_cf.forget_user(user as u32);
+        let forgetting_duration = start.elapsed();
+
+        forgotten_users[user] = true;
+
+        let ratings_without_users: Vec<(u32, u32, f64)> = ratings.iter()
+            .filter(|(other_user, _item, _rating)| !forgotten_users[*other_user as usize])
+            .cloned()
+            .collect();
+
+        let mut weighted_cf_without_user =
+            WeightedItembasedCF::new(num_items, 10, WeightedSimilarity::AdjustedCosine);
+
+        let start = Instant::now();
+        weighted_cf_without_user.partial_fit(&ratings_without_users);
+        let retraining_duration = start.elapsed();
+
+        println!("{},{},{},{},{}", dataset_file, user, interactions[user].1.len(),
+            forgetting_duration.as_millis(), retraining_duration.as_millis());
+    }
+}
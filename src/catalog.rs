@@ -0,0 +1,293 @@
+use std::hash::Hash;
+use fnv::{FnvHashMap, FnvHashSet};
+
+use crate::IncrementalDecrementalModel;
+use crate::itembased::ItembasedCF;
+
+/// Maps arbitrary external item keys (e.g., strings or u64 ids) to the dense internal item ids
+/// used by our models. Retired ids are reused for new keys, so that the catalog does not grow
+/// without bounds when items come and go.
+#[derive(Debug)]
+pub struct ItemCatalog<K: Hash + Eq + Clone> {
+    indices: FnvHashMap<K, u32>,
+    keys: Vec<Option<K>>,
+    free_indices: Vec<u32>,
+}
+
+impl<K: Hash + Eq + Clone> ItemCatalog<K> {
+
+    pub fn new() -> Self {
+        ItemCatalog {
+            indices: FnvHashMap::default(),
+            keys: Vec::new(),
+            free_indices: Vec::new(),
+        }
+    }
+
+    /// Returns the internal id of a key, assigning a new id if we have not seen the key yet
+    pub fn index_or_insert(&mut self, key: &K) -> u32 {
+        if let Some(index) = self.indices.get(key) {
+            return *index;
+        }
+
+        let index = match self.free_indices.pop() {
+            Some(index) => {
+                self.keys[index as usize] = Some(key.clone());
+                index
+            },
+            None => {
+                self.keys.push(Some(key.clone()));
+                (self.keys.len() - 1) as u32
+            }
+        };
+
+        self.indices.insert(key.clone(), index);
+        index
+    }
+
+    /// Returns the internal id of a key, if the key is part of the catalog
+    pub fn index(&self, key: &K) -> Option<u32> {
+        self.indices.get(key).cloned()
+    }
+
+    /// Returns the external key for an internal id, if the id is in use
+    pub fn key(&self, index: u32) -> Option<&K> {
+        self.keys.get(index as usize).and_then(|key| key.as_ref())
+    }
+
+    /// Removes the key for an id from the catalog and makes the id available for reuse
+    pub fn retire(&mut self, index: u32) {
+        if let Some(key) = self.keys.get_mut(index as usize).and_then(|key| key.take()) {
+            self.indices.remove(&key);
+            self.free_indices.push(index);
+        }
+    }
+
+    /// Number of keys currently in the catalog
+    pub fn len(&self) -> usize {
+        self.indices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+}
+
+impl<K: Hash + Eq + Clone> Default for ItemCatalog<K> {
+    fn default() -> Self {
+        ItemCatalog::new()
+    }
+}
+
+/// `ItembasedCF` on external item keys. The keys are mapped to internal ids via an
+/// `ItemCatalog`, and the ids of items whose interactions have all been forgotten are retired from
+/// the catalog after every forget, so that the model reuses their state for new keys.
+#[derive(Debug)]
+pub struct KeyedItembasedCF<K: Hash + Eq + Clone> {
+    catalog: ItemCatalog<K>,
+    itembased_cf: ItembasedCF,
+}
+
+impl<K: Hash + Eq + Clone> KeyedItembasedCF<K> {
+
+    pub fn new(k: usize) -> Self {
+        KeyedItembasedCF::with_model(ItembasedCF::new(0, k))
+    }
+
+    /// Wraps an empty model, e.g., one with a different similarity measure or user cap
+    pub fn with_model(mut itembased_cf: ItembasedCF) -> Self {
+        assert_eq!(itembased_cf.num_items(), 0, "The model must not contain any items yet");
+        itembased_cf.track_retired_items();
+
+        KeyedItembasedCF { catalog: ItemCatalog::new(), itembased_cf }
+    }
+
+    pub fn catalog(&self) -> &ItemCatalog<K> {
+        &self.catalog
+    }
+
+    /// Adds individual `(user, key)` interactions, see `ItembasedCF::partial_fit_interactions`
+    pub fn partial_fit_interactions(&mut self, interactions: &[(u32, K)]) {
+        let catalog = &mut self.catalog;
+        let indexed_interactions: Vec<(u32, u32)> = interactions.iter()
+            .map(|(user, key)| (*user, catalog.index_or_insert(key)))
+            .collect();
+
+        self.itembased_cf.partial_fit_interactions(&indexed_interactions);
+    }
+
+    /// Forgets a single interaction, see `ItembasedCF::forget_interaction`
+    pub fn forget_interaction(&mut self, user: u32, key: &K) {
+        if let Some(item) = self.catalog.index(key) {
+            self.itembased_cf.forget_interaction(user, item);
+            self.retire_items();
+        }
+    }
+
+    /// Forgets all interactions of a user, see `ItembasedCF::forget_user`
+    pub fn forget_user(&mut self, user: u32) {
+        self.itembased_cf.forget_user(user);
+        self.retire_items();
+    }
+
+    /// Recommends the `n` highest scoring unseen items for a history, see
+    /// `ItembasedCF::recommend`. Keys which are not part of the catalog are ignored.
+    pub fn recommend(&self, history: &[K], n: usize) -> Vec<(K, f32)> {
+        let catalog = &self.catalog;
+        let indexed_history: Vec<u32> = history.iter()
+            .filter_map(|key| catalog.index(key))
+            .collect();
+
+        self.itembased_cf.recommend(&indexed_history, n).into_iter()
+            .map(|(item, score)| (catalog.key(item).unwrap().clone(), score))
+            .collect()
+    }
+
+    fn retire_items(&mut self) {
+        for item in self.itembased_cf.take_retired_items() {
+            self.catalog.retire(item);
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone> IncrementalDecrementalModel<(u32, Vec<K>), K, FnvHashSet<K>>
+    for KeyedItembasedCF<K> {
+
+    /// Adds the histories of users as `(user, keys)`, see `ItembasedCF::partial_fit`
+    fn partial_fit(&mut self, histories: &[(u32, Vec<K>)]) {
+        let catalog = &mut self.catalog;
+        let indexed_histories: Vec<(u32, Vec<u32>)> = histories.iter()
+            .map(|(user, history)| {
+                (*user, history.iter().map(|key| catalog.index_or_insert(key)).collect())
+            })
+            .collect();
+
+        self.itembased_cf.partial_fit(&indexed_histories);
+    }
+
+    /// Forgets a history which was added via `partial_fit`, and retires the items which have no
+    /// interactions left. Histories with unknown keys cannot have been added and are ignored.
+    fn forget(&mut self, interactions: &(u32, Vec<K>)) {
+        let (user, history) = interactions;
+        let catalog = &self.catalog;
+        let indexed_history: Option<Vec<u32>> = history.iter()
+            .map(|key| catalog.index(key))
+            .collect();
+
+        if let Some(indexed_history) = indexed_history {
+            self.itembased_cf.forget(&(*user, indexed_history));
+            self.retire_items();
+        }
+    }
+
+    fn predict(&self, key: &K) -> FnvHashSet<K> {
+        match self.catalog.index(key) {
+            Some(item) => {
+                self.itembased_cf.predict(&item).iter()
+                    .map(|other_item| self.catalog.key(*other_item).unwrap().clone())
+                    .collect()
+            },
+            None => FnvHashSet::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use fnv::FnvHashSet;
+
+    use crate::IncrementalDecrementalModel;
+    use crate::catalog::{ItemCatalog, KeyedItembasedCF};
+    use crate::itembased::ItembasedCF;
+    use crate::similarity::Similarity;
+
+    #[test]
+    fn reuses_retired_indices() {
+        let mut catalog: ItemCatalog<String> = ItemCatalog::new();
+
+        let a = catalog.index_or_insert(&"a".to_string());
+        let b = catalog.index_or_insert(&"b".to_string());
+        assert_eq!((a, b), (0, 1));
+        assert_eq!(catalog.index_or_insert(&"a".to_string()), 0);
+
+        catalog.retire(a);
+        assert_eq!(catalog.index(&"a".to_string()), None);
+        assert_eq!(catalog.key(a), None);
+        assert_eq!(catalog.len(), 1);
+
+        let c = catalog.index_or_insert(&"c".to_string());
+        assert_eq!(c, 0);
+        assert_eq!(catalog.key(c), Some(&"c".to_string()));
+    }
+
+    #[test]
+    fn keyed_interactions_with_itembased_cf() {
+        let mut keyed_cf: KeyedItembasedCF<u64> = KeyedItembasedCF::new(2);
+
+        let histories: Vec<(u32, Vec<u64>)> = vec![
+            (0, vec![1_000_000_007, 42]),
+            (1, vec![42, 123_456_789_000]),
+        ];
+
+        keyed_cf.partial_fit(&histories);
+        assert_eq!(keyed_cf.itembased_cf.num_items(), 3);
+        assert!(keyed_cf.predict(&42).contains(&1_000_000_007));
+
+        // The item which is only part of the forgotten history is retired from the catalog
+        keyed_cf.forget(&histories[0]);
+        assert_eq!(keyed_cf.catalog().index(&1_000_000_007), None);
+        assert_eq!(keyed_cf.catalog().len(), 2);
+        assert!(!keyed_cf.predict(&42).contains(&1_000_000_007));
+
+        // Histories with unknown keys are ignored
+        keyed_cf.forget(&(0, vec![1_000_000_007, 42]));
+
+        // Its internal id is reused for the next new key, so the model does not grow
+        keyed_cf.partial_fit(&[(2, vec![7, 42])]);
+        assert_eq!(keyed_cf.catalog().index(&7), Some(0));
+        assert_eq!(keyed_cf.itembased_cf.num_items(), 3);
+        assert!(keyed_cf.predict(&7).contains(&42));
+
+        let recommendations = keyed_cf.recommend(&[7], 2);
+        assert_eq!(recommendations[0].0, 42);
+
+        // The keyed model is the same as a model on the internal ids
+        let mut itembased_cf = ItembasedCF::new(3, 2);
+        itembased_cf.partial_fit(&[(1, vec![1, 2]), (2, vec![0, 1])]);
+        for key in &[7, 42, 123_456_789_000] {
+            let item = keyed_cf.catalog().index(key).unwrap();
+            let neighbours: Vec<u32> = keyed_cf.predict(key).iter()
+                .map(|other_key| keyed_cf.catalog().index(other_key).unwrap())
+                .collect();
+            assert_eq!(neighbours.into_iter().collect::<FnvHashSet<u32>>(),
+                itembased_cf.predict(&item));
+        }
+    }
+
+    #[test]
+    fn keyed_interactions_retire_items() {
+        let model = ItembasedCF::with_similarity(0, 2, Similarity::Cosine);
+        let mut keyed_cf: KeyedItembasedCF<String> = KeyedItembasedCF::with_model(model);
+
+        keyed_cf.partial_fit_interactions(&[
+            (0, "a".to_string()), (0, "b".to_string()),
+            (1, "b".to_string()), (1, "c".to_string()),
+        ]);
+        assert_eq!(keyed_cf.catalog().len(), 3);
+
+        keyed_cf.forget_interaction(0, &"a".to_string());
+        assert_eq!(keyed_cf.catalog().index(&"a".to_string()), None);
+
+        // Unknown keys are ignored
+        keyed_cf.forget_interaction(0, &"z".to_string());
+
+        keyed_cf.forget_user(1);
+        assert_eq!(keyed_cf.catalog().len(), 1);
+        assert!(keyed_cf.predict(&"b".to_string()).is_empty());
+
+        keyed_cf.partial_fit_interactions(&[(2, "d".to_string()), (2, "b".to_string())]);
+        assert_eq!(keyed_cf.catalog().len(), 2);
+        assert!(keyed_cf.predict(&"d".to_string()).contains("b"));
+    }
+}
@@ -0,0 +1,160 @@
+use ndarray::{Array1, Array2};
+use ndarray_linalg::{Diag, SolveTriangular, UPLO};
+use ndarray_linalg::Cholesky as Factorize;
+
+/// Cholesky decomposition `A = LL'` of a symmetric positive definite matrix, which supports
+/// rank-one updates `A + xx'` and downdates `A - xx'` in O(d²) instead of refactorizing in O(d³).
+/// The factorization and the solves use LAPACK via `ndarray-linalg`, only the updates are our own.
+#[derive(Debug, Clone)]
+pub struct Cholesky {
+    l: Array2<f64>,
+}
+
+impl Cholesky {
+
+    /// Decomposes a symmetric matrix, returns `None` if it is not positive definite
+    pub fn new(a: &Array2<f64>) -> Option<Self> {
+        a.cholesky(UPLO::Lower)
+            .ok()
+            .map(|l| Cholesky { l })
+    }
+
+    /// Updates the decomposition to the one of `A + xx'`. Returns false if rounding errors make
+    /// the result numerically not positive definite, e.g., for non-finite values in `x`, the
+    /// decomposition is unusable afterwards and has to be recomputed.
+    pub fn update(&mut self, x: &Array1<f64>) -> bool {
+        self.rank_one(x, 1.0)
+    }
+
+    /// Updates the decomposition to the one of `A - xx'`. Returns false if the result is not
+    /// positive definite (at least numerically), the decomposition is unusable afterwards and
+    /// has to be recomputed.
+    pub fn downdate(&mut self, x: &Array1<f64>) -> bool {
+        self.rank_one(x, -1.0)
+    }
+
+    /// Sequence of Givens rotations, see Golub and Van Loan, "Matrix Computations"
+    fn rank_one(&mut self, x: &Array1<f64>, sign: f64) -> bool {
+        let mut x = x.clone();
+        let size = x.len();
+
+        for k in 0..size {
+            let diagonal = self.l[[k, k]];
+            let squared_norm = diagonal * diagonal + sign * x[k] * x[k];
+
+            if squared_norm.is_nan() || squared_norm <= 0.0 {
+                return false;
+            }
+
+            let norm = squared_norm.sqrt();
+            let cosine = norm / diagonal;
+            let sine = x[k] / diagonal;
+            self.l[[k, k]] = norm;
+
+            for row in (k + 1)..size {
+                self.l[[row, k]] = (self.l[[row, k]] + sign * sine * x[row]) / cosine;
+                x[row] = cosine * x[row] - sine * self.l[[row, k]];
+            }
+        }
+
+        true
+    }
+
+    /// Squared ratio of the largest and the smallest diagonal entry of `L`, a cheap lower bound
+    /// on the condition number of `A`
+    pub fn condition_estimate(&self) -> f64 {
+        let diagonal = self.l.diag();
+        let largest = diagonal.iter().cloned().fold(0.0, f64::max);
+        let smallest = diagonal.iter().cloned().fold(f64::INFINITY, f64::min);
+
+        (largest / smallest).powi(2)
+    }
+
+    /// Solves `Ax = b` via forward and backward substitution
+    pub fn solve(&self, b: &Array1<f64>) -> Array1<f64> {
+        let y = self.l.solve_triangular(UPLO::Lower, Diag::NonUnit, b)
+            .expect("Unable to solve Ly = b");
+        self.l.t().solve_triangular(UPLO::Upper, Diag::NonUnit, &y)
+            .expect("Unable to solve L'x = y")
+    }
+
+    /// Solves `AX = B` for every column of `B`
+    pub fn solve_columns(&self, b: &Array2<f64>) -> Array2<f64> {
+        let y = self.l.solve_triangular(UPLO::Lower, Diag::NonUnit, b)
+            .expect("Unable to solve LY = B");
+        self.l.t().solve_triangular(UPLO::Upper, Diag::NonUnit, &y)
+            .expect("Unable to solve L'X = Y")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use ndarray::{Array1, Array2};
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::StdRng;
+
+    use crate::cholesky::Cholesky;
+
+    fn random_vector(rng: &mut StdRng, size: usize) -> Array1<f64> {
+        Array1::from_shape_fn(size, |_| rng.gen_range(-1.0, 1.0))
+    }
+
+    fn add_outer_product(a: &mut Array2<f64>, x: &Array1<f64>, weight: f64) {
+        for ((row, column), value) in a.indexed_iter_mut() {
+            *value += weight * x[row] * x[column];
+        }
+    }
+
+    fn assert_close(cholesky: &Cholesky, other: &Cholesky) {
+        for (value, other_value) in cholesky.l.iter().zip(other.l.iter()) {
+            assert!((value - other_value).abs() < 0.000000001);
+        }
+    }
+
+    #[test]
+    fn updates_and_downdates_match_decomposition() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let size = 5;
+
+        let mut a = Array2::eye(size);
+        let mut cholesky = Cholesky::new(&a).unwrap();
+
+        let vectors: Vec<Array1<f64>> = (0..10).map(|_| random_vector(&mut rng, size)).collect();
+
+        for x in vectors.iter() {
+            add_outer_product(&mut a, x, 1.0);
+            assert!(cholesky.update(x));
+        }
+        assert_close(&cholesky, &Cholesky::new(&a).unwrap());
+
+        for x in vectors[..7].iter() {
+            add_outer_product(&mut a, x, -1.0);
+            assert!(cholesky.downdate(x));
+        }
+        assert_close(&cholesky, &Cholesky::new(&a).unwrap());
+
+        let b = random_vector(&mut rng, size);
+        let x = cholesky.solve(&b);
+        for (value, expected) in a.dot(&x).iter().zip(b.iter()) {
+            assert!((value - expected).abs() < 0.000000001);
+        }
+    }
+
+    #[test]
+    fn detects_loss_of_definiteness() {
+        let a: Array2<f64> = Array2::eye(2);
+        assert!(Cholesky::new(&(-&a)).is_none());
+
+        let mut cholesky = Cholesky::new(&a).unwrap();
+        assert!(!cholesky.downdate(&Array1::from_vec(vec![1.0, 0.0])));
+    }
+
+    #[test]
+    fn condition_estimate_of_diagonal_matrix() {
+        let mut a = Array2::zeros((3, 3));
+        a.diag_mut().assign(&Array1::from_vec(vec![4.0, 1.0, 0.01]));
+        let cholesky = Cholesky::new(&a).unwrap();
+        assert!((cholesky.condition_estimate() - 400.0).abs() < 0.000001);
+    }
+}
@@ -0,0 +1,154 @@
+use crate::IncrementalDecrementalModel;
+
+/// Evaluation result for a single fold
+#[derive(Debug, Clone, PartialEq)]
+pub struct FoldMetrics {
+    pub fold: usize,
+    pub num_examples: usize,
+    /// Mean of the per-example metric over the examples of the fold
+    pub metric: f64,
+}
+
+/// k-fold cross-validation via decremental updates. The model must already be trained on all
+/// examples. For every fold, we forget its examples, evaluate the model on them and add them back
+/// again, so no additional models have to be trained. Example `i` belongs to fold
+/// `i % num_folds`, so the examples should be shuffled beforehand.
+pub fn k_fold<T, I, O, M, E>(
+    model: &mut M,
+    examples: &[T],
+    num_folds: usize,
+    metric: E)
+    -> Vec<FoldMetrics>
+    where M: IncrementalDecrementalModel<T, I, O>, T: Clone, E: Fn(&M, &T) -> f64 {
+
+    assert!(num_folds > 1 && num_folds <= examples.len(),
+        "The number of folds must be between two and the number of examples");
+
+    let mut folds = Vec::with_capacity(num_folds);
+
+    for fold in 0..num_folds {
+        let fold_examples: Vec<T> = examples.iter()
+            .skip(fold)
+            .step_by(num_folds)
+            .cloned()
+            .collect();
+
+        for example in fold_examples.iter() {
+            model.forget(example);
+        }
+
+        let total: f64 = fold_examples.iter()
+            .map(|example| metric(model, example))
+            .sum();
+
+        model.partial_fit(&fold_examples);
+
+        folds.push(FoldMetrics {
+            fold,
+            num_examples: fold_examples.len(),
+            metric: total / fold_examples.len() as f64,
+        });
+    }
+
+    folds
+}
+
+/// Leave-one-out cross-validation via decremental updates, returns the metric for every example
+pub fn leave_one_out<T, I, O, M, E>(model: &mut M, examples: &[T], metric: E) -> Vec<f64>
+    where M: IncrementalDecrementalModel<T, I, O>, T: Clone, E: Fn(&M, &T) -> f64 {
+
+    k_fold(model, examples, examples.len(), metric)
+        .into_iter()
+        .map(|fold| fold.metric)
+        .collect()
+}
+
+/// Mean of the metric over all examples of all folds
+pub fn mean_metric(folds: &[FoldMetrics]) -> f64 {
+    let num_examples: usize = folds.iter().map(|fold| fold.num_examples).sum();
+    let total: f64 = folds.iter().map(|fold| fold.metric * fold.num_examples as f64).sum();
+    total / num_examples as f64
+}
+
+#[cfg(test)]
+mod tests {
+
+    use fnv::FnvHashMap;
+
+    use crate::IncrementalDecrementalModel;
+    use crate::cross_validation::{k_fold, leave_one_out, mean_metric};
+    use crate::mnb::{MultinomialNaiveBayes, MNBFeatures};
+
+    fn examples() -> Vec<(MNBFeatures, u8)> {
+        let pairs: [(&[(u32, u32)], u8); 7] = [
+            (&[(0, 2), (1, 1)], 0),
+            (&[(0, 1), (3, 1)], 0),
+            (&[(1, 2), (0, 1)], 0),
+            (&[(2, 3), (4, 1)], 1),
+            (&[(3, 1), (4, 2)], 1),
+            (&[(2, 1), (3, 2)], 1),
+            (&[(0, 1), (4, 1)], 1),
+        ];
+
+        pairs.iter()
+            .map(|(features, label)| {
+                let features: FnvHashMap<u32, u32> = features.iter().cloned().collect();
+                (MNBFeatures::new(features), *label)
+            })
+            .collect()
+    }
+
+    fn zero_one_loss(mnb: &MultinomialNaiveBayes, example: &(MNBFeatures, u8)) -> f64 {
+        let (features, label) = example;
+        if mnb.predict(features) == *label { 0.0 } else { 1.0 }
+    }
+
+    #[test]
+    fn folds_match_separately_trained_models() {
+        let examples = examples();
+        let num_folds = 3;
+
+        let mut mnb = MultinomialNaiveBayes::new(2, 5);
+        mnb.partial_fit(&examples);
+
+        let folds = k_fold(&mut mnb, &examples, num_folds, zero_one_loss);
+
+        assert_eq!(folds.len(), num_folds);
+        assert_eq!(folds.iter().map(|fold| fold.num_examples).collect::<Vec<_>>(), vec![3, 2, 2]);
+
+        for fold in folds.iter() {
+            let (test, train): (Vec<_>, Vec<_>) = examples.iter().cloned()
+                .enumerate()
+                .partition(|(index, _example)| index % num_folds == fold.fold);
+
+            let train: Vec<(MNBFeatures, u8)> = train.into_iter().map(|(_, ex)| ex).collect();
+            let mut separate_mnb = MultinomialNaiveBayes::new(2, 5);
+            separate_mnb.partial_fit(&train);
+
+            let total: f64 = test.iter()
+                .map(|(_index, example)| zero_one_loss(&separate_mnb, example))
+                .sum();
+            assert_eq!(fold.metric, total / test.len() as f64);
+        }
+
+        // All examples have been added back again
+        let mut retrained = MultinomialNaiveBayes::new(2, 5);
+        retrained.partial_fit(&examples);
+        assert_eq!(mnb, retrained);
+    }
+
+    #[test]
+    fn leave_one_out_per_example() {
+        let examples = examples();
+
+        let mut mnb = MultinomialNaiveBayes::new(2, 5);
+        mnb.partial_fit(&examples);
+
+        let losses = leave_one_out(&mut mnb, &examples, zero_one_loss);
+        assert_eq!(losses.len(), examples.len());
+
+        let folds = k_fold(&mut mnb, &examples, examples.len(), zero_one_loss);
+        let mean_loss = losses.iter().sum::<f64>() / losses.len() as f64;
+        assert_eq!(mean_metric(&folds), mean_loss);
+    }
+}
@@ -23,9 +23,44 @@ pub fn read_interactions(dataset_file: &'static str, num_users: usize) -> Vec<(u
                 let user_idx = user - 1;
                 let item_idx = item - 1;
 
-                if interactions[user_idx as usize].1.len() < 500 {
-                    interactions[user_idx as usize].1.push(item_idx);
-                }
+                // The cap on the number of interactions per user is applied by the models
+                interactions[user_idx as usize].1.push(item_idx);
+            }
+        });
+
+    interactions
+}
+
+
+/// Reads `user item [rating]` interactions, interactions without a rating get a weight of one
+pub fn read_rated_interactions(
+    dataset_file: &'static str,
+    num_users: usize
+) -> Vec<(u32, Vec<(u32, f64)>)> {
+
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .delimiter(b' ')
+        .from_path(dataset_file)
+        .expect("Unable to read input file");
+
+    let mut interactions: Vec<(u32, Vec<(u32, f64)>)> = (0 .. num_users)
+        .map(|index| (index as u32, Vec::new())).collect();
+
+    reader.records()
+        .for_each(|result| {
+            if let Ok(record) = result {
+                let user: u32 = record[0].parse().expect("Unable to parse user!");
+                let item: u32 = record[1].parse().expect("Unable to parse item!");
+                let rating: f64 = record.get(2)
+                    .map(|rating| rating.parse().expect("Unable to parse rating!"))
+                    .unwrap_or(1.0);
+
+                let user_idx = user - 1;
+                let item_idx = item - 1;
+
+                interactions[user_idx as usize].1.push((item_idx, rating));
             }
         });
 
@@ -2,21 +2,42 @@ extern crate timely;
 extern crate differential_dataflow;
 
 use differential_dataflow::input::InputSession;
+use differential_dataflow::Collection;
 use timely::worker::Worker;
 use timely::communication::Allocator;
-use timely::dataflow::ProbeHandle;
+use timely::dataflow::{ProbeHandle, Scope};
 use timely::progress::Timestamp;
 use timely::progress::timestamp::Refines;
 use timely::order::TotalOrder;
 use differential_dataflow::lattice::Lattice;
 
-use differential_dataflow::operators::{Join,CountTotal,Count};
+use differential_dataflow::operators::{Join,CountTotal,Count,Threshold,Reduce};
 use differential_dataflow::operators::arrange::ArrangeByKey;
 use differential_dataflow::operators::join::JoinCore;
 
+use fnv::FnvHashMap;
+
+use crate::itembased::ScoredItem;
+use crate::similarity::Similarity;
+use crate::user_cap::UserCap;
+
+/// Number of cooccurrences of an item pair, number of interactions with both items and number of
+/// users with at least one interaction (only tracked for measures which need it)
+pub type PairCounts = (isize, isize, isize, isize);
+
+/// Dot product of the weight vectors of an item pair and the squared norms of both vectors
+pub type WeightedPairCounts = (isize, isize, isize);
+
+/// Maintains the top-`k` most similar items for every item, only considering pairs with a
+/// similarity above `threshold`. With a threshold of `f64::NEG_INFINITY`, the neighbourhoods are
+/// the same as the ones returned by `ItembasedCF::predict`.
 pub fn itembased_cf<T>(
     worker: &mut Worker<Allocator>,
-    interactions_input: &mut InputSession<T, (u32, u32), isize>)
+    interactions_input: &mut InputSession<T, (u32, u32), isize>,
+    similarity: Similarity,
+    user_cap: UserCap,
+    threshold: f64,
+    k: usize)
     -> ProbeHandle<T>
     where T: Timestamp + TotalOrder + Lattice + Refines<()> {
 
@@ -24,61 +45,597 @@ pub fn itembased_cf<T>(
 
         let interactions = interactions_input.to_collection(scope);
 
-        // Find all users with less than 500 interactions
-        let users_with_enough_interactions = interactions
-            .map(|(user, _item)| user)
-            .count_total()
-            .filter(move |(_user, count): &(u32, isize)| *count < 500)
-            .map(|(user, _count)| user);
+        let pair_counts = item_pair_counts(&interactions, similarity, user_cap);
+        let neighbours = top_k_neighbours(&pair_counts, similarity, threshold, k);
+
+        // Compute the similarity between item pairs, has to be done in a map due to the lack of a
+        // total order for f64 (which seems to break the consolidation in join)
+        let similarities = neighbours
+            .map(move |(item_a, (item_b, (num_cooc, occ_a, occ_b, num_users)))| {
+                let score = similarity.score(
+                    num_cooc as u64, occ_a as u64, occ_b as u64, num_users as u64);
+                ((item_a, item_b), score)
+            });
+
+        similarities.probe()
+    });
+
+    probe
+}
+
+/// Maintains the top-`n` recommendations for every user, as `(user, (rank, item))` tuples,
+/// computed from the top-`k` neighbourhoods of the items in their histories
+pub fn itembased_recommendations<T>(
+    worker: &mut Worker<Allocator>,
+    interactions_input: &mut InputSession<T, (u32, u32), isize>,
+    similarity: Similarity,
+    user_cap: UserCap,
+    threshold: f64,
+    k: usize,
+    n: usize)
+    -> ProbeHandle<T>
+    where T: Timestamp + TotalOrder + Lattice + Refines<()> {
+
+    let probe = worker.dataflow(|scope| {
+
+        let interactions = interactions_input.to_collection(scope);
+
+        let pair_counts = item_pair_counts(&interactions, similarity, user_cap);
+        let neighbours = top_k_neighbours(&pair_counts, similarity, threshold, k);
+
+        recommend(&interactions, &neighbours, similarity, n).probe()
+    });
+
+    probe
+}
+
+/// Item-based CF on weighted interactions, where the weight of a `(user, item)` interaction is
+/// given by its multiplicity in the input, e.g., inserted via `update((user, item), rating)`.
+/// Changing a rating amounts to updating the multiplicity by the difference.
+///
+/// As the weights are multiplicities, they have to be integers, and only the plain cosine is
+/// supported. Fractional weights or the adjusted cosine require `WeightedItembasedCF`.
+pub fn weighted_itembased_cf<T>(
+    worker: &mut Worker<Allocator>,
+    weighted_interactions_input: &mut InputSession<T, (u32, u32), isize>,
+    threshold: f64)
+    -> ProbeHandle<T>
+    where T: Timestamp + TotalOrder + Lattice + Refines<()> {
+
+    let probe = worker.dataflow(|scope| {
+
+        let weighted_interactions = weighted_interactions_input.to_collection(scope);
+
+        let similarities = weighted_dot_products(&weighted_interactions)
+            // Compute the cosine similarity, has to be done in a map due to the lack of a
+            // total order for f64
+            .map(|((item_a, item_b), (dot, norm_a, norm_b))| {
+                ((item_a, item_b), weighted_cosine(dot, norm_a, norm_b))
+            })
+            .filter(move |(_item_pair, cosine)| *cosine > threshold);
+
+        similarities.probe()
+    });
+
+    probe
+}
+
+/// Dataflow stage which maintains the dot product of the weight vectors of every item pair with
+/// item_a > item_b, together with their squared norms. Joining the weighted interactions with
+/// themselves multiplies the weights, so counting the joined item pairs gives us the dot products.
+pub fn weighted_dot_products<G>(
+    weighted_interactions: &Collection<G, (u32, u32)>)
+    -> Collection<G, ((u32, u32), WeightedPairCounts)>
+    where G: Scope, G::Timestamp: Lattice + Ord {
+
+    let arranged_weighted_interactions = weighted_interactions.arrange_by_key();
+
+    // Sum of the products of the weights of all users which interacted with both items
+    let dot_products = arranged_weighted_interactions
+        .join_core(&arranged_weighted_interactions, |_user, &item_a, &item_b| {
+            if item_a > item_b { Some((item_a, item_b)) } else { None }
+        })
+        .count();
+
+    // Sum of the squared weights per item
+    let squared_norms = arranged_weighted_interactions
+        .join_core(&arranged_weighted_interactions, |_user, &item_a, &item_b| {
+            if item_a == item_b { Some(item_a) } else { None }
+        })
+        .count();
+
+    let arranged_squared_norms = squared_norms.arrange_by_key();
+
+    dot_products
+        // Find the squared norm of item_a
+        .map(|((item_a, item_b), dot)| (item_a, (item_b, dot)))
+        .join_core(
+            &arranged_squared_norms,
+            |&item_a, &(item_b, dot), &norm_a| Some((item_b, (item_a, dot, norm_a)))
+        )
+        // Find the squared norm of item_b
+        .join_core(
+            &arranged_squared_norms,
+            |&item_b, &(item_a, dot, norm_a), &norm_b| {
+                Some(((item_a, item_b), (dot, norm_a, norm_b)))
+            },
+        )
+}
+
+/// Cosine similarity from a dot product and the squared norms of the weight vectors
+fn weighted_cosine(dot: isize, squared_norm_a: isize, squared_norm_b: isize) -> f64 {
+    dot as f64 / (squared_norm_a as f64 * squared_norm_b as f64).sqrt()
+}
+
+/// Dataflow stage which keeps the `k` most similar items with a similarity above `threshold` for
+/// every item, as `(item_a, (item_b, counts))`. The counts have a total order in contrast to the
+/// similarities, so we keep them around instead of the scores. The reduce only holds at most `k`
+/// neighbours per item, so the size of its output stays bounded when data is added or removed.
+/// We rank the scores in f32 with the same ordering as `ItembasedCF`, so that ties and near-ties
+/// are broken in favor of the smaller item id in both implementations.
+pub fn top_k_neighbours<G>(
+    pair_counts: &Collection<G, ((u32, u32), PairCounts)>,
+    similarity: Similarity,
+    threshold: f64,
+    k: usize)
+    -> Collection<G, (u32, (u32, PairCounts))>
+    where G: Scope, G::Timestamp: Lattice + Ord {
+
+    // Index the pairs by item in both directions, asymmetric measures already contain both
+    let candidates = pair_counts
+        .flat_map(move |((item_a, item_b), (num_cooc, occ_a, occ_b, num_users))| {
+            let mut candidates = vec![(item_a, (item_b, (num_cooc, occ_a, occ_b, num_users)))];
+            if similarity.is_symmetric() {
+                candidates.push((item_b, (item_a, (num_cooc, occ_b, occ_a, num_users))));
+            }
+            candidates
+        });
+
+    candidates
+        .reduce(move |_item, candidates, neighbours| {
+            let mut scored_candidates: Vec<(ScoredItem, PairCounts)> = candidates.iter()
+                .filter(|(_candidate, count)| *count > 0)
+                .filter_map(|((other_item, counts), _count)| {
+                    let (num_cooc, occ_a, occ_b, num_users) = *counts;
+                    let score = similarity.score(num_cooc as u64, occ_a as u64, occ_b as u64,
+                        num_users as u64);
+
+                    if score > threshold {
+                        Some((ScoredItem { item: *other_item, score: score as f32 }, *counts))
+                    } else {
+                        None
+                    }
+                })
+                .collect();
+
+            // ScoredItem is ordered by descending score
+            scored_candidates.sort_by(|(scored_item_a, _), (scored_item_b, _)| {
+                scored_item_a.cmp(scored_item_b)
+            });
+
+            for (scored_item, counts) in scored_candidates.into_iter().take(k) {
+                neighbours.push(((scored_item.item, counts), 1));
+            }
+        })
+}
+
+/// Dataflow stage which aggregates the similarities of the neighbours of all items in a user's
+/// history, excludes the items which the user has already seen and ranks the remaining items.
+/// The neighbours are given as `(item_a, (item_b, counts))` like the output of
+/// `top_k_neighbours`, where the score of item_b for item_a is computed from the counts. We
+/// aggregate and rank the scores exactly like `ItembasedCF::recommend`, i.e., we sum the f32
+/// similarities in f64 and break ties of the summed f32 scores in favor of the smaller item id.
+pub fn recommend<G>(
+    interactions: &Collection<G, (u32, u32)>,
+    neighbours: &Collection<G, (u32, (u32, PairCounts))>,
+    similarity: Similarity,
+    n: usize)
+    -> Collection<G, (u32, (u32, u32))>
+    where G: Scope, G::Timestamp: Lattice + Ord {
+
+    let distinct_interactions = interactions.distinct();
+
+    // Every item in the history of a user votes for its neighbours
+    let candidates = distinct_interactions
+        .map(|(user, item)| (item, user))
+        .join_map(neighbours, |_item, &user, &(other_item, counts)| {
+            ((user, other_item), counts)
+        })
+        // Remove items which the user has already seen
+        .antijoin(&distinct_interactions)
+        .map(|((user, other_item), counts)| (user, (other_item, counts)));
+
+    candidates
+        .reduce(move |_user, votes, ranked| {
+            let mut scores: FnvHashMap<u32, f64> = FnvHashMap::default();
+
+            for ((other_item, (num_cooc, occ_a, occ_b, num_users)), num_votes) in votes.iter() {
+                let score = similarity.score(*num_cooc as u64, *occ_a as u64, *occ_b as u64,
+                    *num_users as u64) as f32;
+                *scores.entry(*other_item).or_insert(0.0) += *num_votes as f64 * score as f64;
+            }
+
+            let mut scored_items: Vec<ScoredItem> = scores.into_iter()
+                .map(|(item, score)| ScoredItem { item, score: score as f32 })
+                .collect();
+
+            // ScoredItem is ordered by descending score
+            scored_items.sort();
+
+            for (rank, scored_item) in scored_items.into_iter().take(n).enumerate() {
+                ranked.push(((rank as u32, scored_item.item), 1));
+            }
+        })
+}
+
+/// Computes the counts required for scoring all cooccurring item pairs. Symmetric measures
+/// only produce pairs with item_a > item_b, asymmetric measures produce both directions.
+fn item_pair_counts<G>(
+    interactions: &Collection<G, (u32, u32)>,
+    similarity: Similarity,
+    user_cap: UserCap)
+    -> Collection<G, ((u32, u32), PairCounts)>
+    where G: Scope, G::Timestamp: Lattice + TotalOrder {
+
+    // Apply the cap to the distinct items of each user, exactly like ItembasedCF does. The
+    // reduce gets the items in sorted order and is re-evaluated whenever a user's items change,
+    // so users which drop below the cap again are included automatically.
+    let remaining_interactions: Collection<G, (u32, u32)> = interactions
+        .reduce(move |&user, items, capped_items| {
+            let distinct_items: Vec<u32> = items.iter()
+                .filter(|(_item, count)| *count > 0)
+                .map(|(item, _count)| **item)
+                .collect();
 
-        // Remove users with too many interactions
-        let remaining_interactions = interactions
-            .semijoin(&users_with_enough_interactions);
+            for item in user_cap.apply(user, &distinct_items).iter() {
+                capped_items.push((*item, 1));
+            }
+        });
 
-        let num_interactions_per_item = remaining_interactions
-            .map(|(_user, item)| item)
+    let num_interactions_per_item = remaining_interactions
+        .map(|(_user, item)| item)
+        .count_total();
+
+    let arranged_remaining_interactions = remaining_interactions.arrange_by_key();
+
+    // Asymmetric measures need the counts in both directions
+    let symmetric = similarity.is_symmetric();
+
+    // Compute the number of cooccurrences of each item pair
+    let cooccurrences = arranged_remaining_interactions
+        .join_core(&arranged_remaining_interactions, move |_user, &item_a, &item_b| {
+            if item_a > item_b || (!symmetric && item_a < item_b) {
+                Some((item_a, item_b))
+            } else {
+                None
+            }
+        })
+        .count();
+
+    let arranged_num_interactions_per_item = num_interactions_per_item.arrange_by_key();
+
+    // Collect the number of cooccurrences of each item pair together with the number of
+    // interactions of both items
+    let pair_counts = cooccurrences
+        // Find the number of interactions for item_a
+        .map(|((item_a, item_b), num_cooc)| (item_a, (item_b, num_cooc)))
+        .join_core(
+            &arranged_num_interactions_per_item,
+            |&item_a, &(item_b, num_cooc), &occ_a| Some((item_b, (item_a, num_cooc, occ_a)))
+        )
+        // Find the number of interactions for item_b
+        .join_core(
+            &arranged_num_interactions_per_item,
+            |&item_b, &(item_a, num_cooc, occ_a), &occ_b| {
+                Some(((item_a, item_b), (num_cooc, occ_a, occ_b)))
+            },
+        );
+
+    // Some measures additionally need the number of users with at least one interaction. We
+    // only join with it if required, as the join routes all pairs through a single key.
+    if similarity.depends_on_num_users() {
+        let num_users = remaining_interactions
+            .map(|(user, _item)| user)
+            .distinct()
+            .map(|_user| ())
             .count_total();
 
-        let arranged_remaining_interactions = remaining_interactions.arrange_by_key();
+        pair_counts
+            .map(|(item_pair, counts)| ((), (item_pair, counts)))
+            .join_map(&num_users, |_, &(item_pair, (num_cooc, occ_a, occ_b)), &num_users| {
+                (item_pair, (num_cooc, occ_a, occ_b, num_users))
+            })
+    } else {
+        pair_counts
+            .map(|(item_pair, (num_cooc, occ_a, occ_b))| {
+                (item_pair, (num_cooc, occ_a, occ_b, 0))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::cell::RefCell;
+    use std::collections::BTreeMap;
+    use std::rc::Rc;
+
+    use differential_dataflow::{Collection, Data};
+    use differential_dataflow::input::InputSession;
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::StdRng;
+    use timely::communication::Allocator;
+    use timely::dataflow::scopes::Child;
+    use timely::worker::Worker;
+
+    use crate::differential::itembased::{item_pair_counts, recommend, top_k_neighbours};
+    use crate::differential::itembased::{weighted_cosine, weighted_dot_products};
+    use crate::itembased::ItembasedCF;
+    use crate::IncrementalDecrementalModel;
+    use crate::similarity::Similarity;
+    use crate::user_cap::{CapPolicy, UserCap};
+    use crate::weighted_itembased::{WeightedItembasedCF, WeightedSimilarity};
+
+    type TestScope<'a> = Child<'a, Worker<Allocator>, usize>;
+
+    /// Runs a dataflow stage on the interactions in a single worker, and returns its output after
+    /// each batch of updates, consolidated and sorted
+    fn evaluate<D, F>(batches: Vec<Vec<((u32, u32), isize)>>, stage: F) -> Vec<Vec<D>>
+        where D: Data + Send,
+              F: for<'a> Fn(&Collection<TestScope<'a>, (u32, u32)>) -> Collection<TestScope<'a>, D>
+                  + Send + Sync + 'static {
+
+        let results = timely::execute(timely::Configuration::Thread, move |worker| {
+
+            let mut interactions_input: InputSession<usize, (u32, u32), isize> =
+                InputSession::new();
+
+            let output = Rc::new(RefCell::new(BTreeMap::new()));
+            let output_in_dataflow = output.clone();
+
+            let probe = worker.dataflow(|scope| {
+                stage(&interactions_input.to_collection(scope))
+                    .inspect(move |(data, _time, diff)| {
+                        *output_in_dataflow.borrow_mut().entry(data.clone()).or_insert(0) += diff;
+                    })
+                    .probe()
+            });
+
+            let mut snapshots = Vec::with_capacity(batches.len());
+
+            for (epoch, batch) in batches.iter().enumerate() {
+                for (interaction, diff) in batch.iter() {
+                    interactions_input.update(*interaction, *diff);
+                }
+
+                interactions_input.advance_to(epoch + 1);
+                interactions_input.flush();
+
+                worker.step_while(|| probe.less_than(interactions_input.time()));
+
+                let snapshot: Vec<D> = output.borrow().iter()
+                    .filter(|(_data, count)| **count > 0)
+                    .map(|(data, _count)| data.clone())
+                    .collect();
+                snapshots.push(snapshot);
+            }
+
+            snapshots
+        });
+
+        results.unwrap().join().into_iter().next().unwrap().unwrap()
+    }
+
+    fn interactions() -> Vec<(u32, u32)> {
+        vec![
+            (0, 0), (0, 1), (0, 2),
+            (1, 0), (1, 1),
+            (2, 1), (2, 3),
+            (3, 2), (3, 3),
+            (4, 0), (4, 4),
+        ]
+    }
+
+    fn inserted(interactions: &[(u32, u32)]) -> Vec<((u32, u32), isize)> {
+        interactions.iter().map(|interaction| (*interaction, 1)).collect()
+    }
+
+    fn removed(interactions: &[(u32, u32)]) -> Vec<((u32, u32), isize)> {
+        interactions.iter().map(|interaction| (*interaction, -1)).collect()
+    }
+
+    /// The ranked items per user, like `ItembasedCF::recommend` returns them
+    fn in_memory_recommendations(
+        itembased_cf: &ItembasedCF,
+        interactions: &[(u32, u32)],
+        n: usize)
+        -> Vec<(u32, (u32, u32))>
+    {
+        let mut histories: BTreeMap<u32, Vec<u32>> = BTreeMap::new();
+        for (user, item) in interactions.iter() {
+            histories.entry(*user).or_insert_with(Vec::new).push(*item);
+        }
 
-        // Compute the number of cooccurrences of each item pair
-        let cooccurrences = arranged_remaining_interactions
-            .join_core(&arranged_remaining_interactions, |_user, &item_a, &item_b| {
-                if item_a > item_b { Some((item_a, item_b)) } else { None }
+        histories.iter()
+            .flat_map(|(user, history)| {
+                itembased_cf.recommend(history, n).into_iter()
+                    .enumerate()
+                    .map(move |(rank, (item, _score))| (*user, (rank as u32, item)))
             })
-            .count();
-
-        let arranged_num_interactions_per_item = num_interactions_per_item.arrange_by_key();
-
-        // Compute the jaccard similarity between item pairs (= number of users that interacted
-        // with both items / number of users that interacted with at least one of the items)
-        let jaccard_similarities = cooccurrences
-            // Find the number of interactions for item_a
-            .map(|((item_a, item_b), num_cooc)| (item_a, (item_b, num_cooc)))
-            .join_core(
-                &arranged_num_interactions_per_item,
-                |&item_a, &(item_b, num_cooc), &occ_a| Some((item_b, (item_a, num_cooc, occ_a)))
-            )
-            // Find the number of interactions for item_b
-            .join_core(
-                &arranged_num_interactions_per_item,
-                |&item_b, &(item_a, num_cooc, occ_a), &occ_b| {
-                    Some(((item_a, item_b), (num_cooc, occ_a, occ_b)))
-                },
-            )
-            // Compute Jaccard similarity, has to be done in a map due to the lack of a
-            // total order for f64 (which seems to break the consolidation in join)
-            .map(|((item_a, item_b), (num_cooc, occ_a, occ_b))| {
-                let jaccard = num_cooc as f64 / (occ_a + occ_b - num_cooc) as f64;
-                ((item_a, item_b), jaccard)
+            .collect()
+    }
+
+    #[test]
+    fn recommendations_match_in_memory_recommendations() {
+        let (k, n) = (2, 2);
+        let interactions = interactions();
+        let forgotten_interactions = [(0, 2), (4, 4)];
+
+        let remaining_interactions: Vec<(u32, u32)> = interactions.iter()
+            .filter(|interaction| !forgotten_interactions.contains(interaction))
+            .cloned()
+            .collect();
+
+        for similarity in &[Similarity::Jaccard, Similarity::Cosine] {
+            let similarity = *similarity;
+
+            let batches = vec![inserted(&interactions), removed(&forgotten_interactions)];
+            let recommendations = evaluate(batches, move |interactions| {
+                let pair_counts = item_pair_counts(interactions, similarity, UserCap::unlimited());
+                let neighbours = top_k_neighbours(&pair_counts, similarity, f64::NEG_INFINITY, k);
+                recommend(interactions, &neighbours, similarity, n)
             });
 
-        // We threshold the similarity matrix
-        let thresholded_similarities = jaccard_similarities
-            .filter(|(_item_pair, jaccard)| *jaccard > 0.05);
+            let mut itembased_cf = ItembasedCF::with_similarity(5, k, similarity);
+            itembased_cf.partial_fit_interactions(&interactions);
+            assert_eq!(recommendations[0],
+                in_memory_recommendations(&itembased_cf, &interactions, n));
 
-        thresholded_similarities.probe()
-    });
+            for (user, item) in forgotten_interactions.iter() {
+                itembased_cf.forget_interaction(*user, *item);
+            }
+            assert_eq!(recommendations[1],
+                in_memory_recommendations(&itembased_cf, &remaining_interactions, n));
+        }
+    }
 
-    probe
-}
\ No newline at end of file
+    #[test]
+    fn weighted_similarities_match_in_memory_similarities() {
+        let ratings: Vec<(u32, u32, isize)> = vec![
+            (0, 0, 5), (0, 1, 3), (0, 2, 4),
+            (1, 0, 3), (1, 1, 1), (1, 3, 2),
+            (2, 1, 4), (2, 2, 5), (2, 3, 1),
+            (3, 0, 2), (3, 3, 4),
+        ];
+
+        // The weights are the multiplicities, changing a rating updates them by the difference
+        let batches = vec![
+            ratings.iter().map(|(user, item, rating)| ((*user, *item), *rating)).collect(),
+            vec![((0, 2), 1 - 4)],
+            vec![((1, 3), -2), ((3, 0), -2)],
+        ];
+
+        let dot_products = evaluate(batches, |interactions| weighted_dot_products(interactions));
+
+        let mut model = WeightedItembasedCF::new(4, 2, WeightedSimilarity::Cosine);
+        let weighted_ratings: Vec<(u32, u32, f64)> = ratings.iter()
+            .map(|(user, item, rating)| (*user, *item, *rating as f64))
+            .collect();
+        model.partial_fit(&weighted_ratings);
+
+        let mut in_memory_similarities = vec![model.similarities()];
+        model.partial_fit(&[(0, 2, 1.0)]);
+        in_memory_similarities.push(model.similarities());
+        model.forget(&(1, 3, 2.0));
+        model.forget(&(3, 0, 2.0));
+        in_memory_similarities.push(model.similarities());
+
+        for (snapshot, similarities) in dot_products.iter().zip(in_memory_similarities.iter()) {
+            assert_eq!(snapshot.len(), similarities.len());
+            for ((item_pair, (dot, norm_a, norm_b)), (other_item_pair, similarity)) in
+                snapshot.iter().zip(similarities.iter()) {
+                assert_eq!(item_pair, other_item_pair);
+                let cosine = weighted_cosine(*dot, *norm_a, *norm_b);
+                assert!((cosine as f32 - similarity).abs() < 0.000001);
+            }
+        }
+    }
+
+    #[test]
+    fn sampled_user_cap_matches_in_memory_pair_counts() {
+        let user_cap = UserCap::new(3, CapPolicy::Sample);
+
+        // Users 0 and 1 exceed the cap, user 0 drops back below it after forgetting
+        let interactions: Vec<(u32, u32)> = vec![
+            (0, 0), (0, 1), (0, 2), (0, 3), (0, 4),
+            (1, 0), (1, 1), (1, 2), (1, 3), (1, 4), (1, 5),
+            (2, 1), (2, 2),
+            (3, 0), (3, 4), (3, 5),
+        ];
+        let forgotten_interactions = [(0, 1), (0, 3), (3, 4)];
+
+        let batches = vec![inserted(&interactions), removed(&forgotten_interactions)];
+        let pair_counts = evaluate(batches, move |interactions| {
+            item_pair_counts(interactions, Similarity::Jaccard, user_cap)
+                .map(|(item_pair, (num_cooc, occ_a, occ_b, _num_users))| {
+                    (item_pair, (num_cooc as u32, occ_a as u32, occ_b as u32))
+                })
+        });
+
+        let mut interactions_cf = ItembasedCF::with_user_cap(6, 2, Similarity::Jaccard, user_cap);
+        interactions_cf.partial_fit_interactions(&interactions);
+
+        let mut histories: BTreeMap<u32, Vec<u32>> = BTreeMap::new();
+        for (user, item) in interactions.iter() {
+            histories.entry(*user).or_insert_with(Vec::new).push(*item);
+        }
+        let histories: Vec<(u32, Vec<u32>)> = histories.into_iter().collect();
+
+        let mut itembased_cf = ItembasedCF::with_user_cap(6, 2, Similarity::Jaccard, user_cap);
+        itembased_cf.partial_fit(&histories);
+
+        assert_eq!(pair_counts[0], interactions_cf.pair_counts());
+        assert_eq!(pair_counts[0], itembased_cf.pair_counts());
+
+        for (user, item) in forgotten_interactions.iter() {
+            interactions_cf.forget_interaction(*user, *item);
+        }
+        assert_eq!(pair_counts[1], interactions_cf.pair_counts());
+    }
+
+    #[test]
+    fn neighbours_match_in_memory_neighbours() {
+        let k = 2;
+        let similarities = [Similarity::Jaccard, Similarity::Cosine,
+            Similarity::ConditionalProbability, Similarity::LogLikelihoodRatio];
+
+        for seed in 0..5 {
+            let mut rng = StdRng::seed_from_u64(seed);
+
+            // Few users and items lead to many ties between the similarities
+            let mut interactions: Vec<(u32, u32)> = (0..30)
+                .map(|_| (rng.gen_range(0, 10), rng.gen_range(0, 8)))
+                .collect();
+            interactions.sort();
+            interactions.dedup();
+
+            let forgotten_interactions: Vec<(u32, u32)> = interactions.iter()
+                .filter(|(user, _item)| *user < 3)
+                .cloned()
+                .collect();
+
+            for similarity in similarities.iter() {
+                let similarity = *similarity;
+
+                let batches = vec![inserted(&interactions), removed(&forgotten_interactions)];
+                let neighbours = evaluate(batches, move |interactions| {
+                    let pair_counts =
+                        item_pair_counts(interactions, similarity, UserCap::unlimited());
+                    top_k_neighbours(&pair_counts, similarity, f64::NEG_INFINITY, k)
+                        .map(|(item, (other_item, _counts))| (item, other_item))
+                });
+
+                let mut itembased_cf = ItembasedCF::with_similarity(8, k, similarity);
+                itembased_cf.partial_fit_interactions(&interactions);
+                assert_eq!(neighbours[0], in_memory_neighbours(&itembased_cf));
+
+                for (user, item) in forgotten_interactions.iter() {
+                    itembased_cf.forget_interaction(*user, *item);
+                }
+                assert_eq!(neighbours[1], in_memory_neighbours(&itembased_cf));
+            }
+        }
+    }
+
+    /// The neighbours of all items as `(item, other_item)`, sorted like the dataflow output
+    fn in_memory_neighbours(itembased_cf: &ItembasedCF) -> Vec<(u32, u32)> {
+        let mut neighbours: Vec<(u32, u32)> = (0..itembased_cf.num_items() as u32)
+            .flat_map(|item| {
+                itembased_cf.predict(&item).into_iter().map(move |other_item| (item, other_item))
+            })
+            .collect();
+
+        neighbours.sort();
+        neighbours
+    }
+}
@@ -0,0 +1,238 @@
+extern crate timely;
+extern crate differential_dataflow;
+
+use differential_dataflow::input::InputSession;
+use differential_dataflow::Collection;
+use timely::worker::Worker;
+use timely::communication::Allocator;
+use timely::dataflow::{ProbeHandle, Scope};
+use timely::progress::Timestamp;
+use timely::progress::timestamp::Refines;
+use timely::order::TotalOrder;
+use differential_dataflow::lattice::Lattice;
+
+use differential_dataflow::operators::{Join,Count,Reduce};
+
+/// Maintains the `n` most likely next items for every item as `(item, (rank, next_item))`
+/// tuples, like `MarkovChain::recommend_next`. The input consists of `(user, position, item)`
+/// interactions, where consecutive positions of a user form a transition. Removing an interaction
+/// retracts exactly the transitions into and out of its position.
+pub fn markov_chain<T>(
+    worker: &mut Worker<Allocator>,
+    sessions_input: &mut InputSession<T, (u32, u32, u32), isize>,
+    n: usize)
+    -> ProbeHandle<T>
+    where T: Timestamp + TotalOrder + Lattice + Refines<()> {
+
+    let probe = worker.dataflow(|scope| {
+
+        let sessions = sessions_input.to_collection(scope);
+
+        let transitions = transition_counts(&sessions);
+
+        next_items(&transitions, n).probe()
+    });
+
+    probe
+}
+
+/// Dataflow stage which counts the transitions `(item, next_item)` between the items at
+/// consecutive positions of each user, consecutive repetitions of an item are ignored
+pub fn transition_counts<G>(
+    sessions: &Collection<G, (u32, u32, u32)>)
+    -> Collection<G, ((u32, u32), isize)>
+    where G: Scope, G::Timestamp: Lattice + Ord {
+
+    let items_by_position = sessions
+        .map(|(user, position, item)| ((user, position), item));
+
+    // Key every item by the position which follows it
+    let previous_items_by_position = sessions
+        .map(|(user, position, item)| ((user, position + 1), item));
+
+    items_by_position
+        .join_map(&previous_items_by_position, |_user_and_position, &next_item, &item| {
+            (item, next_item)
+        })
+        .filter(|(item, next_item)| item != next_item)
+        .count()
+}
+
+/// Dataflow stage which ranks the next items of every item by their transition counts, ties are
+/// broken in favor of the smaller item id. The counts are integers, so we can rank them directly
+/// in the reduce.
+pub fn next_items<G>(
+    transitions: &Collection<G, ((u32, u32), isize)>,
+    n: usize)
+    -> Collection<G, (u32, (u32, u32))>
+    where G: Scope, G::Timestamp: Lattice + Ord {
+
+    transitions
+        .map(|((item, next_item), count)| (item, (next_item, count)))
+        .reduce(move |_item, candidates, ranked| {
+            let mut next_items: Vec<(u32, isize)> = candidates.iter()
+                .filter(|(_candidate, multiplicity)| *multiplicity > 0)
+                .map(|((next_item, count), _multiplicity)| (*next_item, *count))
+                .collect();
+
+            next_items.sort_by(|(item_a, count_a), (item_b, count_b)| {
+                count_b.cmp(count_a).then(item_a.cmp(item_b))
+            });
+
+            for (rank, (next_item, _count)) in next_items.into_iter().take(n).enumerate() {
+                ranked.push(((rank as u32, next_item), 1));
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::cell::RefCell;
+    use std::collections::BTreeMap;
+    use std::rc::Rc;
+
+    use differential_dataflow::{Collection, Data};
+    use differential_dataflow::input::InputSession;
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::StdRng;
+    use timely::communication::Allocator;
+    use timely::dataflow::scopes::Child;
+    use timely::worker::Worker;
+
+    use crate::differential::markov::{next_items, transition_counts};
+    use crate::IncrementalDecrementalModel;
+    use crate::markov::MarkovChain;
+
+    type TestScope<'a> = Child<'a, Worker<Allocator>, usize>;
+
+    /// Runs a dataflow stage on the `(user, position, item)` interactions in a single worker, and
+    /// returns its output after each batch of updates, consolidated and sorted
+    fn evaluate<D, F>(batches: Vec<Vec<((u32, u32, u32), isize)>>, stage: F) -> Vec<Vec<D>>
+        where D: Data + Send,
+              F: for<'a> Fn(&Collection<TestScope<'a>, (u32, u32, u32)>)
+                  -> Collection<TestScope<'a>, D> + Send + Sync + 'static {
+
+        let results = timely::execute(timely::Configuration::Thread, move |worker| {
+
+            let mut sessions_input: InputSession<usize, (u32, u32, u32), isize> =
+                InputSession::new();
+
+            let output = Rc::new(RefCell::new(BTreeMap::new()));
+            let output_in_dataflow = output.clone();
+
+            let probe = worker.dataflow(|scope| {
+                stage(&sessions_input.to_collection(scope))
+                    .inspect(move |(data, _time, diff)| {
+                        *output_in_dataflow.borrow_mut().entry(data.clone()).or_insert(0) += diff;
+                    })
+                    .probe()
+            });
+
+            let mut snapshots = Vec::with_capacity(batches.len());
+
+            for (epoch, batch) in batches.iter().enumerate() {
+                for (interaction, diff) in batch.iter() {
+                    sessions_input.update(*interaction, *diff);
+                }
+
+                sessions_input.advance_to(epoch + 1);
+                sessions_input.flush();
+
+                worker.step_while(|| probe.less_than(sessions_input.time()));
+
+                let snapshot: Vec<D> = output.borrow().iter()
+                    .filter(|(_data, count)| **count > 0)
+                    .map(|(data, _count)| data.clone())
+                    .collect();
+                snapshots.push(snapshot);
+            }
+
+            snapshots
+        });
+
+        results.unwrap().join().into_iter().next().unwrap().unwrap()
+    }
+
+    /// The positioned interactions of the sessions, the index of a session is its user
+    fn positioned(sessions: &[Vec<u32>], diff: isize) -> Vec<((u32, u32, u32), isize)> {
+        sessions.iter().enumerate()
+            .flat_map(|(user, session)| {
+                session.iter().enumerate()
+                    .map(move |(position, item)| ((user as u32, position as u32, *item), diff))
+            })
+            .collect()
+    }
+
+    /// The ranked next items of all items as `(item, (rank, next_item))`, sorted like the
+    /// dataflow output
+    fn in_memory_next_items(markov_chain: &MarkovChain, num_items: u32, n: usize)
+        -> Vec<(u32, (u32, u32))> {
+
+        (0..num_items)
+            .flat_map(|item| {
+                markov_chain.recommend_next(item, n).into_iter()
+                    .enumerate()
+                    .map(move |(rank, (next_item, _probability))| (item, (rank as u32, next_item)))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn transitions_match_in_memory_transitions() {
+        let (num_items, n) = (6, 2);
+
+        for seed in 0..5 {
+            let mut rng = StdRng::seed_from_u64(seed);
+
+            // Few items lead to repeated transitions and ties between the counts
+            let sessions: Vec<Vec<u32>> = (0..10)
+                .map(|_| {
+                    let length = rng.gen_range(0, 8);
+                    (0..length).map(|_| rng.gen_range(0, num_items)).collect()
+                })
+                .collect();
+
+            // The first three users are forgotten completely, and the next three lose the tail
+            // of their sessions, which retracts the transitions into the removed positions
+            let updated_sessions: Vec<Vec<u32>> = sessions.iter().enumerate()
+                .map(|(user, session)| {
+                    match user {
+                        0..=2 => Vec::new(),
+                        3..=5 => session[..session.len() / 2].to_vec(),
+                        _ => session.clone(),
+                    }
+                })
+                .collect();
+
+            let mut retractions = positioned(&sessions, -1);
+            retractions.retain(|((user, position, _item), _diff)| {
+                *position as usize >= updated_sessions[*user as usize].len()
+            });
+
+            let batches = vec![positioned(&sessions, 1), retractions];
+
+            let counts = evaluate(batches.clone(), |sessions| {
+                transition_counts(sessions)
+                    .map(|(transition, count)| (transition, count as u32))
+            });
+            let ranked = evaluate(batches, move |sessions| {
+                next_items(&transition_counts(sessions), n)
+            });
+
+            let mut markov_chain = MarkovChain::new(num_items as usize, n);
+            markov_chain.partial_fit(&sessions);
+
+            assert_eq!(counts[0], markov_chain.transition_counts());
+            assert_eq!(ranked[0], in_memory_next_items(&markov_chain, num_items, n));
+
+            for (session, updated_session) in sessions.iter().zip(updated_sessions.iter()) {
+                markov_chain.forget(session);
+                markov_chain.partial_fit(&[updated_session.clone()]);
+            }
+
+            assert_eq!(counts[1], markov_chain.transition_counts());
+            assert_eq!(ranked[1], in_memory_next_items(&markov_chain, num_items, n));
+        }
+    }
+}
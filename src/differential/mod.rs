@@ -1,6 +1,7 @@
 
 pub mod itembased;
 pub mod lsh;
+pub mod markov;
 pub mod mnb;
 
 pub mod io_utils;
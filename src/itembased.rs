@@ -1,39 +1,549 @@
 extern crate fnv;
 
+use std::borrow::Cow;
 use std::cmp::Ordering;
 use fnv::{FnvHashMap, FnvHashSet};
 use std::collections::BinaryHeap;
+use std::thread;
 
 use crate::IncrementalDecrementalModel;
+use crate::similarity::Similarity;
+use crate::user_cap::UserCap;
+
+/// An item pair with its cooccurrence count and the numbers of interactions with both items
+#[cfg(test)]
+pub(crate) type CountedPair = ((u32, u32), (u32, u32, u32));
 
 #[derive(Debug)]
 pub struct ItembasedCF {
     k: usize,
+    similarity: Similarity,
+    user_cap: UserCap,
     c: Vec<FnvHashMap<u32, u32>>,
     s: Vec<FnvHashMap<u32, f32>>,
+    /// The k most similar items per item, sorted by descending similarity
+    neighbours: Vec<Vec<ScoredItem>>,
+    /// Items whose neighbour list may miss an item after a neighbour got less similar
+    stale_neighbours: FnvHashSet<u32>,
     n: Vec<u32>,
+    num_users: u32,
+    user_histories: FnvHashMap<u32, Vec<u32>>,
+    /// Ids of forgotten items, only recorded after `track_retired_items` was called
+    retired_items: Option<Vec<u32>>,
 }
 
 impl ItembasedCF {
 
     pub fn new(num_items: usize, k: usize) -> ItembasedCF {
+        ItembasedCF::with_similarity(num_items, k, Similarity::Jaccard)
+    }
+
+    pub fn with_similarity(num_items: usize, k: usize, similarity: Similarity) -> ItembasedCF {
+        ItembasedCF::with_user_cap(num_items, k, similarity, UserCap::unlimited())
+    }
+
+    pub fn with_user_cap(
+        num_items: usize,
+        k: usize,
+        similarity: Similarity,
+        user_cap: UserCap)
+    -> ItembasedCF {
         let c = vec![FnvHashMap::with_capacity_and_hasher(0, Default::default()); num_items];
         let s = vec![FnvHashMap::with_capacity_and_hasher(0, Default::default()); num_items];
+        let neighbours = vec![Vec::new(); num_items];
         let n = vec![0; num_items];
 
-        ItembasedCF { k, c, s, n }
+        let user_histories = FnvHashMap::with_capacity_and_hasher(0, Default::default());
+
+        ItembasedCF {
+            k,
+            similarity,
+            user_cap,
+            c,
+            s,
+            neighbours,
+            stale_neighbours: FnvHashSet::default(),
+            n,
+            num_users: 0,
+            user_histories,
+            retired_items: None,
+        }
+    }
+
+    /// Current size of the item catalog, the catalog grows whenever we encounter a new item id
+    pub fn num_items(&self) -> usize {
+        self.n.len()
+    }
+
+    /// Starts recording the ids of items whose interactions have been forgotten completely, so
+    /// that they can be collected via `take_retired_items`. The ids are not recorded by default,
+    /// as they would otherwise reveal the forgotten items until somebody collects them.
+    pub fn track_retired_items(&mut self) {
+        if self.retired_items.is_none() {
+            self.retired_items = Some(Vec::new());
+        }
+    }
+
+    /// Returns the ids of all items whose interactions have been forgotten completely since the
+    /// last call, empty if `track_retired_items` was not called. Their state has been removed from
+    /// the model, so the ids can be reused for new items, e.g., by retiring them from an
+    /// `ItemCatalog`.
+    pub fn take_retired_items(&mut self) -> Vec<u32> {
+        let n = &self.n;
+        let mut retired_items: Vec<u32> = match self.retired_items.as_mut() {
+            Some(retired_items) => {
+                retired_items.drain(..).filter(|item| n[*item as usize] == 0).collect()
+            },
+            None => return Vec::new(),
+        };
+
+        retired_items.sort();
+        retired_items.dedup();
+        retired_items
+    }
+
+    /// Cooccurrence counts and numbers of interactions of all cooccurring pairs with
+    /// item_a > item_b, sorted by pair, to compare the model with the differential implementation
+    #[cfg(test)]
+    pub(crate) fn pair_counts(&self) -> Vec<CountedPair> {
+        let mut pair_counts: Vec<CountedPair> = self.c.iter().enumerate()
+            .flat_map(|(item_a, cooccurrences)| {
+                cooccurrences.iter()
+                    .filter(move |(item_b, _count)| (item_a as u32) > **item_b)
+                    .map(move |(item_b, count)| {
+                        let counts = (*count, self.n[item_a], self.n[*item_b as usize]);
+                        ((item_a as u32, *item_b), counts)
+                    })
+            })
+            .collect();
+
+        pair_counts.sort();
+        pair_counts
+    }
+
+    /// Grows the catalog to include the given item id
+    fn ensure_item(&mut self, item: u32) {
+        let num_items = item as usize + 1;
+        if num_items > self.n.len() {
+            self.c.resize(num_items, FnvHashMap::with_capacity_and_hasher(0, Default::default()));
+            self.s.resize(num_items, FnvHashMap::with_capacity_and_hasher(0, Default::default()));
+            self.neighbours.resize(num_items, Vec::new());
+            self.n.resize(num_items, 0);
+        }
+    }
+
+    /// Drops the state of an item without any remaining interactions
+    fn retire(&mut self, item: u32) {
+        debug_assert!(self.c[item as usize].is_empty() && self.s[item as usize].is_empty());
+
+        self.c[item as usize] = FnvHashMap::with_capacity_and_hasher(0, Default::default());
+        self.s[item as usize] = FnvHashMap::with_capacity_and_hasher(0, Default::default());
+        self.neighbours[item as usize] = Vec::new();
+        if let Some(retired_items) = self.retired_items.as_mut() {
+            retired_items.push(item);
+        }
+    }
+
+    /// Adds individual `(user, item)` interactions. In contrast to `partial_fit`, we keep track
+    /// of the history of each user, which allows us to forget single interactions later on.
+    /// Repeated interactions of a user with the same item are only counted once.
+    pub fn partial_fit_interactions(&mut self, interactions: &[(u32, u32)]) {
+        let mut items_to_rescore = FnvHashSet::with_capacity_and_hasher(0, Default::default());
+
+        for (user, item) in interactions.iter() {
+            self.ensure_item(*item);
+
+            let mut history = self.user_histories.remove(user).unwrap_or_default();
+            // Histories are kept sorted, so that the cap does not need to copy them
+            if let Err(position) = history.binary_search(item) {
+                let mut updated_history = Vec::with_capacity(history.len() + 1);
+                updated_history.extend_from_slice(&history[..position]);
+                updated_history.push(*item);
+                updated_history.extend_from_slice(&history[position..]);
+
+                let capped_history = self.user_cap.apply(*user, &history);
+                let updated_capped_history = self.user_cap.apply(*user, &updated_history);
+
+                self.replace_history(&capped_history, &updated_capped_history,
+                    &mut items_to_rescore);
+                history = updated_history;
+            }
+            self.user_histories.insert(*user, history);
+        }
+
+        self.rescore(&items_to_rescore);
+    }
+
+    /// Forgets a single interaction of a user, which was added via `partial_fit_interactions`.
+    /// The cooccurrences of the item with the other items in the user's history are decremented,
+    /// the remainder of the history is kept. If the user was capped before, the interactions
+    /// which fall within the cap again are added back. Unknown interactions are ignored.
+    pub fn forget_interaction(&mut self, user: u32, item: u32) {
+        let position = self.user_histories.get(&user)
+            .and_then(|history| history.binary_search(&item).ok());
+
+        let position = match position {
+            Some(position) => position,
+            None => return,
+        };
+
+        let history = self.user_histories.remove(&user).unwrap();
+        let mut updated_history = history.clone();
+        updated_history.remove(position);
+
+        let capped_history = self.user_cap.apply(user, &history);
+        let updated_capped_history = self.user_cap.apply(user, &updated_history);
+
+        let mut items_to_rescore = FnvHashSet::with_capacity_and_hasher(1, Default::default());
+        self.replace_history(&capped_history, &updated_capped_history, &mut items_to_rescore);
+
+        if !updated_history.is_empty() {
+            self.user_histories.insert(user, updated_history);
+        }
+
+        self.rescore(&items_to_rescore);
+    }
+
+    /// Forgets all interactions of a user, which were added via `partial_fit_interactions`
+    pub fn forget_user(&mut self, user: u32) {
+        if let Some(history) = self.user_histories.remove(&user) {
+            let capped_history = self.user_cap.apply(user, &history);
+
+            let mut items_to_rescore = FnvHashSet::with_capacity_and_hasher(0, Default::default());
+            self.replace_history(&capped_history, &[], &mut items_to_rescore);
+            self.rescore(&items_to_rescore);
+        }
+    }
+
+    /// Updates the counts for a user whose (capped) history changes, by removing the items which
+    /// are not part of the history anymore and adding the new items afterwards
+    fn replace_history(
+        &mut self,
+        history: &[u32],
+        updated_history: &[u32],
+        items_to_rescore: &mut FnvHashSet<u32>
+    ) {
+        let items: FnvHashSet<u32> = history.iter().cloned().collect();
+        let updated_items: FnvHashSet<u32> = updated_history.iter().cloned().collect();
+
+        let mut current_history: Vec<u32> = history.to_vec();
+
+        for item in history.iter() {
+            if !updated_items.contains(item) {
+                let position = current_history.iter().position(|other| other == item).unwrap();
+                current_history.swap_remove(position);
+                self.remove_from_history(&current_history, *item);
+                items_to_rescore.insert(*item);
+            }
+        }
+
+        for item in updated_history.iter() {
+            if !items.contains(item) {
+                self.add_to_history(&current_history, *item);
+                current_history.push(*item);
+                items_to_rescore.insert(*item);
+            }
+        }
+    }
+
+    /// Updates the counts for an item which is added to the history of a user, the history must
+    /// not contain the item yet
+    fn add_to_history(&mut self, history: &[u32], item: u32) {
+        if history.is_empty() {
+            self.num_users += 1;
+        }
+
+        self.n[item as usize] += 1;
+        for other_item in history.iter() {
+            *self.c[item as usize].entry(*other_item).or_insert(0) += 1;
+            *self.c[*other_item as usize].entry(item).or_insert(0) += 1;
+        }
+    }
+
+    /// Updates the counts for an item which is removed from the history of a user, the history
+    /// must not contain the item anymore
+    fn remove_from_history(&mut self, history: &[u32], item: u32) {
+        if history.is_empty() {
+            self.num_users -= 1;
+        }
+
+        self.n[item as usize] -= 1;
+        for other_item in history.iter() {
+            *self.c[item as usize].get_mut(other_item).unwrap() -= 1;
+            *self.c[*other_item as usize].get_mut(&item).unwrap() -= 1;
+
+            if self.c[item as usize][other_item] == 0 {
+                self.remove_pair(item, *other_item);
+            }
+        }
+
+        if self.n[item as usize] == 0 {
+            self.retire(item);
+        }
+    }
+
+    /// Recomputes the similarities of all item pairs which involve one of the given items. All
+    /// pairs in `c` cooccur at least once, as pairs without cooccurrences are removed on forget.
+    /// Measures which depend on the number of users require us to rescore all pairs.
+    fn rescore(&mut self, items: &FnvHashSet<u32>) {
+        if self.similarity.depends_on_num_users() {
+            let all_items: FnvHashSet<u32> = (0..self.n.len() as u32).collect();
+            self.rescore_items(&all_items);
+        } else {
+            self.rescore_items(items);
+        }
+
+        self.refresh_stale_neighbours();
+    }
+
+    /// Multi-threaded variant of `partial_fit`. Every thread owns a contiguous range of items and
+    /// only updates the cooccurrences and similarities in the rows of its items, so no merging
+    /// is required. The counts are integers and every similarity is computed from the same counts
+    /// as in the sequential path, so the resulting model is identical to `partial_fit`.
+    pub fn partial_fit_parallel(&mut self, interactions: &[(u32, Vec<u32>)], num_threads: usize) {
+        assert!(num_threads > 0, "At least one thread is required");
+
+        let user_cap = self.user_cap;
+        let histories: Vec<Cow<[u32]>> = interactions.iter()
+            .map(|(user, user_history)| user_cap.apply(*user, user_history))
+            .collect();
+
+        let mut items_to_rescore = FnvHashSet::with_capacity_and_hasher(0, Default::default());
+        for user_history in histories.iter() {
+            if !user_history.is_empty() {
+                self.num_users += 1;
+            }
+            for item in user_history.iter() {
+                self.ensure_item(*item);
+                items_to_rescore.insert(*item);
+            }
+        }
+
+        let rows_per_thread = std::cmp::max(1, self.n.len().div_ceil(num_threads));
+
+        // Update cooccurrence matrix
+        let histories = &histories;
+        thread::scope(|scope| {
+            let row_chunks = self.c.chunks_mut(rows_per_thread)
+                .zip(self.n.chunks_mut(rows_per_thread))
+                .enumerate();
+
+            for (chunk_index, (cooccurrence_rows, count_rows)) in row_chunks {
+                let first_row = chunk_index * rows_per_thread;
+                let rows = first_row..(first_row + count_rows.len());
+
+                scope.spawn(move || {
+                    for user_history in histories.iter() {
+                        for item_a in user_history.iter() {
+                            if rows.contains(&(*item_a as usize)) {
+                                let row = *item_a as usize - first_row;
+                                count_rows[row] += 1;
+                                for item_b in user_history.iter() {
+                                    if item_a != item_b {
+                                        *cooccurrence_rows[row].entry(*item_b).or_insert(0) += 1;
+                                    }
+                                }
+                            }
+                        }
+                    }
+                });
+            }
+        });
+
+        // Update similarity matrix
+        self.rescore_parallel(&items_to_rescore, rows_per_thread);
+    }
+
+    /// Recomputes the same entries as `rescore`, but every thread only writes the rows of its
+    /// items: the entry for (x, y) changes if x or y is one of the items to rescore.
+    fn rescore_parallel(&mut self, items: &FnvHashSet<u32>, rows_per_thread: usize) {
+        let rescore_all = self.similarity.depends_on_num_users();
+        let similarity = self.similarity;
+        let num_users = self.num_users as u64;
+        let k = self.k;
+        let c = &self.c;
+        let n = &self.n;
+        let row_chunks = self.s.chunks_mut(rows_per_thread)
+            .zip(self.neighbours.chunks_mut(rows_per_thread))
+            .enumerate();
+
+        thread::scope(|scope| {
+            for (chunk_index, (similarity_rows, neighbour_rows)) in row_chunks {
+                let first_row = chunk_index * rows_per_thread;
+
+                scope.spawn(move || {
+                    let rows = similarity_rows.iter_mut().zip(neighbour_rows.iter_mut());
+                    for (row, (similarities, neighbours)) in rows.enumerate() {
+                        let item_a = (first_row + row) as u32;
+                        let rescore_row = rescore_all || items.contains(&item_a);
+                        let n_a = n[item_a as usize] as u64;
+
+                        let mut row_changed = false;
+                        for (item_b, count) in c[item_a as usize].iter() {
+                            if rescore_row || items.contains(item_b) {
+                                let n_b = n[*item_b as usize] as u64;
+                                let score = similarity.score(*count as u64, n_a, n_b, num_users);
+                                similarities.insert(*item_b, score as f32);
+                                row_changed = true;
+                            }
+                        }
+
+                        // A bulk update touches most of the row anyway, so we recompute the list
+                        if row_changed {
+                            *neighbours = top_k(similarities, k);
+                        }
+                    }
+                });
+            }
+        });
+
+        self.refresh_stale_neighbours();
+    }
+
+    /// Rescores the pairs which involve one of the given items. Pairs of two such items are
+    /// only scored once, and only the neighbour lists of the rows we write to are updated.
+    fn rescore_items(&mut self, items: &FnvHashSet<u32>) {
+        let mut dirty_pairs = Vec::new();
+        for item_a in items.iter() {
+            for (item_b, count) in self.c[*item_a as usize].iter() {
+                if item_a < item_b || !items.contains(item_b) {
+                    dirty_pairs.push((*item_a, *item_b, *count as u64));
+                }
+            }
+        }
+
+        let num_users = self.num_users as u64;
+        for (item_a, item_b, count) in dirty_pairs {
+            let n_a = self.n[item_a as usize] as u64;
+            let n_b = self.n[item_b as usize] as u64;
+
+            let similarity_ab = self.similarity.score(count, n_a, n_b, num_users) as f32;
+            let similarity_ba = self.similarity.score(count, n_b, n_a, num_users) as f32;
+
+            self.set_similarity(item_a, item_b, similarity_ab);
+            self.set_similarity(item_b, item_a, similarity_ba);
+        }
+    }
+
+    /// Writes the similarity of `item_b` to `item_a` and updates the neighbour list of `item_a`
+    /// without looking at the rest of the row. The list contains all items of the row as long as
+    /// it is shorter than k, and no item outside of the list is more similar than its last entry.
+    fn set_similarity(&mut self, item_a: u32, item_b: u32, score: f32) {
+        let similarities = &mut self.s[item_a as usize];
+        similarities.insert(item_b, score);
+        let num_similarities = similarities.len();
+
+        let scored_item = ScoredItem { item: item_b, score };
+        let neighbours = &mut self.neighbours[item_a as usize];
+
+        match neighbours.iter().position(|neighbour| neighbour.item == item_b) {
+            Some(position) => {
+                let previous = neighbours.remove(position);
+                // An item outside of the list might now be more similar, which we can only find
+                // out by looking at the full row
+                if scored_item > previous && num_similarities > self.k {
+                    self.stale_neighbours.insert(item_a);
+                }
+                insert_sorted(neighbours, scored_item);
+            },
+            None => {
+                if neighbours.len() < self.k {
+                    insert_sorted(neighbours, scored_item);
+                } else if neighbours.last().is_some_and(|last| scored_item < *last) {
+                    neighbours.pop();
+                    insert_sorted(neighbours, scored_item);
+                }
+            },
+        }
+    }
+
+    /// Recomputes the neighbour lists which lost an entry or where an entry got less similar
+    fn refresh_stale_neighbours(&mut self) {
+        for item in self.stale_neighbours.drain() {
+            self.neighbours[item as usize] = top_k(&self.s[item as usize], self.k);
+        }
+    }
+
+    /// Recommends the `n` highest scoring unseen items for a user history. Every distinct item in
+    /// the history adds its similarity to each of its k most similar items, ties are broken in
+    /// favor of the smaller item id. We sum the scores in f64, so that the result does not depend
+    /// on the order of the history and matches the differential `recommend` stage.
+    pub fn recommend(&self, history: &[u32], n: usize) -> Vec<(u32, f32)> {
+        let seen_items: FnvHashSet<u32> = history.iter().cloned().collect();
+
+        let mut scores: FnvHashMap<u32, f64> = FnvHashMap::default();
+        for item in seen_items.iter() {
+            for neighbour in self.top_k_neighbours(*item).iter() {
+                if !seen_items.contains(&neighbour.item) {
+                    *scores.entry(neighbour.item).or_insert(0.0) += neighbour.score as f64;
+                }
+            }
+        }
+
+        let mut recommendations: Vec<ScoredItem> = scores.into_iter()
+            .map(|(item, score)| ScoredItem { item, score: score as f32 })
+            .collect();
+
+        // ScoredItem is ordered by descending score
+        recommendations.sort();
+        recommendations.truncate(n);
+
+        recommendations.into_iter()
+            .map(|scored_item| (scored_item.item, scored_item.score))
+            .collect()
+    }
+
+    fn top_k_neighbours(&self, item: u32) -> &[ScoredItem] {
+        match self.neighbours.get(item as usize) {
+            Some(neighbours) => neighbours,
+            None => &[],
+        }
+    }
+
+    fn remove_pair(&mut self, item_a: u32, item_b: u32) {
+        for (item, other_item) in &[(item_a, item_b), (item_b, item_a)] {
+            let cooccurrences = &mut self.c[*item as usize];
+            cooccurrences.remove(other_item);
+            if cooccurrences.len() * 4 < cooccurrences.capacity() {
+                cooccurrences.shrink_to_fit();
+            }
+
+            let similarities = &mut self.s[*item as usize];
+            similarities.remove(other_item);
+            if similarities.len() * 4 < similarities.capacity() {
+                similarities.shrink_to_fit();
+            }
+            let num_similarities = similarities.len();
+
+            let neighbours = &mut self.neighbours[*item as usize];
+            if let Some(position) = neighbours.iter().position(|n| n.item == *other_item) {
+                neighbours.remove(position);
+                // Another item of the row moves up into the top-k
+                if num_similarities >= self.k {
+                    self.stale_neighbours.insert(*item);
+                }
+            }
+        }
     }
 }
 
-impl IncrementalDecrementalModel<Vec<u32>, u32, FnvHashSet<u32>> for ItembasedCF {
+impl IncrementalDecrementalModel<(u32, Vec<u32>), u32, FnvHashSet<u32>> for ItembasedCF {
 
-    fn partial_fit(&mut self, interactions: &[Vec<u32>]) {
+    /// Adds the histories of users as `(user, items)`. In contrast to `partial_fit_interactions`,
+    /// we do not keep track of the histories, the user ids are only needed for the user cap.
+    fn partial_fit(&mut self, interactions: &[(u32, Vec<u32>)]) {
 
         let mut items_to_rescore = FnvHashSet::with_capacity_and_hasher(0, Default::default());
 
         // Update cooccurrence matrix
-        for user_history in interactions.iter() {
+        for (user, user_history) in interactions.iter() {
+            let user_history = self.user_cap.apply(*user, user_history);
+            if !user_history.is_empty() {
+                self.num_users += 1;
+            }
             for item_a in user_history.iter() {
+                // Grow the catalog for unseen items, smaller item ids are covered as well
+                self.ensure_item(*item_a);
                 // Remember item for rescoring later
                 items_to_rescore.insert(*item_a);
                 self.n[*item_a as usize] += 1;
@@ -48,18 +558,19 @@ impl IncrementalDecrementalModel<Vec<u32>, u32, FnvHashSet<u32>> for ItembasedCF
         }
 
         // Update similarity matrix
-        for item_a in items_to_rescore.iter() {
-            let n_a = self.n[*item_a as usize] as f32;
-            for (item_b, count) in self.c[*item_a as usize].iter() {
-                let n_b = self.n[*item_b as usize] as f32;
-                let similarity = (*count as f32) / (n_a + n_b - *count as f32);
-                self.s[*item_a as usize].insert(*item_b, similarity);
-                self.s[*item_b as usize].insert(*item_a, similarity);
-            }
-        }
+        self.rescore(&items_to_rescore);
     }
 
-    fn forget(&mut self, user_history: &Vec<u32>) {
+    /// Forgets the history of a user, which must have been added via `partial_fit` with the same
+    /// user id, as the user cap depends on it
+    fn forget(&mut self, interactions: &(u32, Vec<u32>)) {
+
+        let (user, user_history) = interactions;
+        let user_history = self.user_cap.apply(*user, user_history);
+
+        if !user_history.is_empty() {
+            self.num_users -= 1;
+        }
 
         // Update cooccurrence matrix
         for item_a in user_history.iter() {
@@ -69,53 +580,66 @@ impl IncrementalDecrementalModel<Vec<u32>, u32, FnvHashSet<u32>> for ItembasedCF
                     // Update cooccurrence matrix
                     *self.c[*item_a as usize].get_mut(item_b).unwrap() -= 1;
                     *self.c[*item_b as usize].get_mut(item_a).unwrap() -= 1;
+
+                    // Pairs which do not cooccur anymore must be removed physically, as their
+                    // keys would otherwise still reveal which items the user interacted with
+                    if self.c[*item_a as usize][item_b] == 0 {
+                        self.remove_pair(*item_a, *item_b);
+                    }
                 }
             }
         }
 
         // Update similarity matrix
-        for item_a in user_history.iter() {
-            let n_a = self.n[*item_a as usize] as f32;
-            for (item_b, count) in self.c[*item_a as usize].iter() {
-                let n_b = self.n[*item_b as usize] as f32;
-                let similarity = (*count as f32) / (n_a + n_b - *count as f32);
-                self.s[*item_a as usize].insert(*item_b, similarity);
-                self.s[*item_b as usize].insert(*item_a, similarity);
+        let items_to_rescore: FnvHashSet<u32> = user_history.iter().cloned().collect();
+        self.rescore(&items_to_rescore);
+
+        for item in items_to_rescore.iter() {
+            if self.n[*item as usize] == 0 {
+                self.retire(*item);
             }
         }
     }
 
     fn predict(&self, item: &u32) -> FnvHashSet<u32> {
-        // We'll use a heap to keep track of the current top-n scored items
-        let mut top_items = BinaryHeap::with_capacity(self.k);
-
-        for (other_item, similarity) in self.s[*item as usize].iter() {
-            let scored_item = ScoredItem { item: *other_item, score: *similarity };
-
-            if top_items.len() < self.k {
-                top_items.push(scored_item);
-            } else {
-                let mut top = top_items.peek_mut().unwrap();
-                if scored_item < *top {
-                    *top = scored_item;
-                }
-            }
-        }
-
-        let top_k_items: FnvHashSet<u32> = top_items
-            .drain()
+        self.top_k_neighbours(*item)
+            .iter()
             .map(|scored_item| scored_item.item)
-            .collect();
-
-        top_k_items
+            .collect()
     }
 }
 
+/// Finds the `k` items with the highest similarity, sorted by descending similarity
+pub(crate) fn top_k(similarities: &FnvHashMap<u32, f32>, k: usize) -> Vec<ScoredItem> {
+    // We'll use a heap to keep track of the current top-n scored items
+    let mut top_items = BinaryHeap::with_capacity(k);
 
+    for (other_item, similarity) in similarities.iter() {
+        let scored_item = ScoredItem { item: *other_item, score: *similarity };
+
+        if top_items.len() < k {
+            top_items.push(scored_item);
+        } else {
+            let mut top = top_items.peek_mut().unwrap();
+            if scored_item < *top {
+                *top = scored_item;
+            }
+        }
+    }
+
+    // ScoredItem is ordered by descending score
+    top_items.into_sorted_vec()
+}
+
+/// Inserts an item into a list which is sorted by descending score
+fn insert_sorted(scored_items: &mut Vec<ScoredItem>, scored_item: ScoredItem) {
+    let position = scored_items.binary_search(&scored_item).unwrap_or_else(|position| position);
+    scored_items.insert(position, scored_item);
+}
 
 /// Result type used to find the top-k anomalous items per item via a binary heap
-#[derive(PartialEq, Debug)]
-struct ScoredItem {
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub(crate) struct ScoredItem {
     pub item: u32,
     pub score: f32,
 }
@@ -126,8 +650,11 @@ fn cmp_reverse(scored_item_a: &ScoredItem, scored_item_b: &ScoredItem) -> Orderi
     match scored_item_a.score.partial_cmp(&scored_item_b.score) {
         Some(Ordering::Less) => Ordering::Greater,
         Some(Ordering::Greater) => Ordering::Less,
-        Some(Ordering::Equal) => Ordering::Equal,
-        None => Ordering::Equal
+        // Ties are broken by item id to make the top-k items deterministic
+        Some(Ordering::Equal) => scored_item_a.item.cmp(&scored_item_b.item),
+        // Similarities are always finite, but a NaN must never be treated as equal to everything
+        // else, as this would corrupt the heap. We rank NaNs below all other scores instead.
+        None => scored_item_a.score.is_nan().cmp(&scored_item_b.score.is_nan())
     }
 }
 
@@ -141,7 +668,7 @@ impl Ord for ScoredItem {
 
 impl PartialOrd for ScoredItem {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(cmp_reverse(self, other))
+        Some(self.cmp(other))
     }
 }
 
@@ -149,26 +676,34 @@ impl PartialOrd for ScoredItem {
 #[cfg(test)]
 mod tests {
 
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::StdRng;
+    use rand::seq::SliceRandom;
+
+    use fnv::{FnvHashMap, FnvHashSet};
+
     use crate::IncrementalDecrementalModel;
-    use crate::itembased::ItembasedCF;
+    use crate::itembased::{ItembasedCF, ScoredItem, top_k};
+    use crate::similarity::Similarity;
+    use crate::user_cap::{UserCap, CapPolicy};
 
     #[test]
     fn toy_example() {
-        let interactions: Vec<Vec<u32>> = vec![
-            vec![0, 1, 2],
-            vec![0, 2],
-            vec![1, 2]
+        let interactions: Vec<(u32, Vec<u32>)> = vec![
+            (0, vec![0, 1, 2]),
+            (1, vec![0, 2]),
+            (2, vec![1, 2])
         ];
 
         let mut itembased_cf = ItembasedCF::new(3, 2);
 
         itembased_cf.partial_fit(&interactions);
-        itembased_cf.forget(&vec![0, 2]);
+        itembased_cf.forget(&(1, vec![0, 2]));
 
 
-        let interactions2: Vec<Vec<u32>> = vec![
-            vec![0, 1, 2],
-            vec![1, 2]
+        let interactions2: Vec<(u32, Vec<u32>)> = vec![
+            (0, vec![0, 1, 2]),
+            (2, vec![1, 2])
         ];
 
         let mut itembased_cf2 = ItembasedCF::new(3, 2);
@@ -178,4 +713,523 @@ mod tests {
         assert_eq!(itembased_cf.s, itembased_cf2.s);
         assert_eq!(itembased_cf.n, itembased_cf2.n);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn forget_removes_pairs() {
+        let interactions: Vec<(u32, Vec<u32>)> = vec![
+            (0, vec![0, 1]),
+            (1, vec![1, 2]),
+            (2, vec![0, 1, 3])
+        ];
+
+        let mut itembased_cf = ItembasedCF::new(4, 2);
+
+        itembased_cf.partial_fit(&interactions);
+        itembased_cf.forget(&(2, vec![0, 1, 3]));
+        itembased_cf.forget(&(0, vec![0, 1]));
+
+        let mut retrained_cf = ItembasedCF::new(4, 2);
+        retrained_cf.partial_fit(&vec![(1, vec![1, 2])]);
+
+        assert_eq!(itembased_cf.c, retrained_cf.c);
+        assert_eq!(itembased_cf.s, retrained_cf.s);
+        assert_eq!(itembased_cf.n, retrained_cf.n);
+
+        // The derived equality only compares the entries, so we additionally check that no
+        // map holds more keys or capacity than the maps of the retrained model
+        assert_same_keys(&itembased_cf.c, &retrained_cf.c);
+        assert_same_keys(&itembased_cf.s, &retrained_cf.s);
+
+        for item in &[0, 3] {
+            assert!(itembased_cf.c[*item].is_empty());
+            assert!(itembased_cf.s[*item].is_empty());
+            assert_eq!(itembased_cf.c[*item].capacity(), 0);
+            assert_eq!(itembased_cf.s[*item].capacity(), 0);
+        }
+        assert!(!itembased_cf.c[1].contains_key(&0));
+        assert!(!itembased_cf.s[1].contains_key(&3));
+    }
+
+    fn assert_same_keys<V>(maps: &[FnvHashMap<u32, V>], other_maps: &[FnvHashMap<u32, V>]) {
+        assert_eq!(maps.len(), other_maps.len());
+        for (map, other_map) in maps.iter().zip(other_maps.iter()) {
+            let keys: FnvHashSet<u32> = map.keys().cloned().collect();
+            let other_keys: FnvHashSet<u32> = other_map.keys().cloned().collect();
+            assert_eq!(keys, other_keys);
+            assert_eq!(map.len(), other_map.len());
+            assert!(map.capacity() <= std::cmp::max(4 * map.len(), other_map.capacity()));
+        }
+    }
+
+    #[test]
+    fn forget_shrinks_rows() {
+        let large_history: (u32, Vec<u32>) = (0, (0..64).collect());
+
+        let mut itembased_cf = ItembasedCF::new(64, 2);
+        itembased_cf.partial_fit(&[large_history.clone(), (1, vec![0, 1])]);
+
+        let capacity_before = itembased_cf.c[0].capacity();
+        assert!(capacity_before >= 63);
+
+        itembased_cf.forget(&large_history);
+
+        // Only the pair (0, 1) remains, the rows must have been shrunk after the removals
+        assert_eq!(itembased_cf.c[0].len(), 1);
+        assert!(itembased_cf.c[0].capacity() <= 4);
+        assert!(itembased_cf.s[0].capacity() <= 4);
+        for item in 2..64 {
+            assert_eq!(itembased_cf.c[item].capacity(), 0);
+            assert_eq!(itembased_cf.s[item].capacity(), 0);
+        }
+    }
+
+    fn assert_finite_similarities(itembased_cf: &ItembasedCF) {
+        for similarities in itembased_cf.s.iter() {
+            for similarity in similarities.values() {
+                assert!(similarity.is_finite());
+                assert!(*similarity > 0.0 && *similarity <= 1.0);
+            }
+        }
+    }
+
+    #[test]
+    fn random_fit_forget_sequences_keep_similarities_finite() {
+        let num_items = 8;
+
+        for seed in 0..50 {
+            let mut rng = StdRng::seed_from_u64(seed);
+
+            let mut itembased_cf = ItembasedCF::new(num_items, 3);
+            let mut remaining_histories: Vec<(u32, Vec<u32>)> = Vec::new();
+
+            for user in 0..20 {
+                if remaining_histories.is_empty() || rng.gen_range(0, 3) > 0 {
+                    let history_length = rng.gen_range(1, num_items + 1);
+                    let mut history: Vec<u32> = (0..num_items as u32).collect();
+                    history.shuffle(&mut rng);
+                    history.truncate(history_length);
+
+                    itembased_cf.partial_fit(&[(user, history.clone())]);
+                    remaining_histories.push((user, history));
+                } else {
+                    let index = rng.gen_range(0, remaining_histories.len());
+                    let history = remaining_histories.swap_remove(index);
+                    itembased_cf.forget(&history);
+                }
+
+                assert_finite_similarities(&itembased_cf);
+            }
+
+            remaining_histories.shuffle(&mut rng);
+            while let Some(history) = remaining_histories.pop() {
+                itembased_cf.forget(&history);
+                assert_finite_similarities(&itembased_cf);
+
+                let mut retrained_cf = ItembasedCF::new(num_items, 3);
+                retrained_cf.partial_fit(&remaining_histories);
+
+                assert_eq!(itembased_cf.c, retrained_cf.c);
+                assert_eq!(itembased_cf.s, retrained_cf.s);
+                assert_eq!(itembased_cf.n, retrained_cf.n);
+                assert_eq!(itembased_cf.neighbours, retrained_cf.neighbours);
+            }
+        }
+    }
+
+    #[test]
+    fn nan_scores_rank_lowest() {
+        let nan = ScoredItem { item: 0, score: std::f32::NAN };
+        let scored = ScoredItem { item: 1, score: 0.1 };
+
+        // The ordering is reversed for the max-heap, so lower scores compare as greater
+        assert!(nan > scored);
+        assert!(scored < nan);
+    }
+
+    #[test]
+    fn forgetting_matches_retraining_for_all_similarities() {
+        let similarities = [
+            Similarity::Jaccard,
+            Similarity::Cosine,
+            Similarity::ConditionalProbability,
+            Similarity::LogLikelihoodRatio,
+            Similarity::PointwiseMutualInformation,
+        ];
+
+        let num_items = 6;
+
+        for similarity in similarities.iter() {
+            for seed in 0..10 {
+                let mut rng = StdRng::seed_from_u64(seed);
+
+                let histories: Vec<(u32, Vec<u32>)> = (0..12)
+                    .map(|user| {
+                        let mut history: Vec<u32> = (0..num_items as u32).collect();
+                        history.shuffle(&mut rng);
+                        history.truncate(rng.gen_range(1, num_items + 1));
+                        (user, history)
+                    })
+                    .collect();
+
+                let mut itembased_cf = ItembasedCF::with_similarity(num_items, 3, *similarity);
+                itembased_cf.partial_fit(&histories);
+
+                let num_to_forget = rng.gen_range(1, histories.len());
+                for history in histories[..num_to_forget].iter() {
+                    itembased_cf.forget(history);
+                }
+
+                let mut retrained_cf = ItembasedCF::with_similarity(num_items, 3, *similarity);
+                retrained_cf.partial_fit(&histories[num_to_forget..]);
+
+                assert_eq!(itembased_cf.c, retrained_cf.c);
+                assert_eq!(itembased_cf.s, retrained_cf.s);
+                assert_eq!(itembased_cf.n, retrained_cf.n);
+                assert_eq!(itembased_cf.num_users, retrained_cf.num_users);
+
+                for similarities in itembased_cf.s.iter() {
+                    assert!(similarities.values().all(|similarity| similarity.is_finite()));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn conditional_probability_is_asymmetric() {
+        let interactions: Vec<(u32, Vec<u32>)> = vec![
+            (0, vec![0, 1]),
+            (1, vec![0, 2]),
+            (2, vec![0]),
+            (3, vec![1, 2]),
+        ];
+
+        let mut itembased_cf =
+            ItembasedCF::with_similarity(3, 2, Similarity::ConditionalProbability);
+        itembased_cf.partial_fit(&interactions);
+
+        // P(1 | 0) = 1 / 3 and P(0 | 1) = 1 / 2
+        assert_eq!(itembased_cf.s[0][&1], 1.0 / 3.0);
+        assert_eq!(itembased_cf.s[1][&0], 0.5);
+    }
+
+    #[test]
+    fn recommend_for_history() {
+        let interactions: Vec<(u32, Vec<u32>)> = vec![
+            (0, vec![0, 1, 2]),
+            (1, vec![0, 1]),
+            (2, vec![1, 3]),
+            (3, vec![2, 3]),
+            (4, vec![0, 4]),
+        ];
+
+        let mut itembased_cf = ItembasedCF::new(5, 3);
+        itembased_cf.partial_fit(&interactions);
+
+        let recommendations = itembased_cf.recommend(&[0, 1], 3);
+
+        let recommended_items: Vec<u32> = recommendations.iter().map(|(item, _)| *item).collect();
+        assert_eq!(recommended_items, vec![2, 4, 3]);
+
+        // s(0, 2) + s(1, 2) = 1 / 4 + 1 / 4, s(0, 4) = 1 / 3, s(1, 3) = 1 / 4
+        assert!((recommendations[0].1 - 0.5).abs() < 0.00001);
+        assert!((recommendations[1].1 - 1.0 / 3.0).abs() < 0.00001);
+        assert!((recommendations[2].1 - 0.25).abs() < 0.00001);
+        assert!(recommendations.iter().all(|(item, _)| *item != 0 && *item != 1));
+
+        assert_eq!(itembased_cf.recommend(&[0, 1], 1).len(), 1);
+    }
+
+    #[test]
+    fn forget_single_interactions() {
+        let interactions: Vec<(u32, u32)> = vec![
+            (0, 0), (0, 1), (0, 2),
+            (1, 0), (1, 2),
+            (2, 1), (2, 2), (2, 3),
+            (3, 3),
+        ];
+
+        let mut itembased_cf = ItembasedCF::new(4, 2);
+        itembased_cf.partial_fit_interactions(&interactions);
+
+        itembased_cf.forget_interaction(0, 1);
+        itembased_cf.forget_interaction(2, 3);
+        itembased_cf.forget_interaction(3, 3);
+
+        // Unknown users and interactions are ignored
+        itembased_cf.forget_interaction(3, 3);
+        itembased_cf.forget_interaction(0, 3);
+        itembased_cf.forget_interaction(42, 0);
+
+        let mut retrained_cf = ItembasedCF::new(4, 2);
+        retrained_cf.partial_fit_interactions(&[(0, 0), (0, 2), (1, 0), (1, 2), (2, 1), (2, 2)]);
+
+        assert_eq!(itembased_cf.c, retrained_cf.c);
+        assert_eq!(itembased_cf.s, retrained_cf.s);
+        assert_eq!(itembased_cf.n, retrained_cf.n);
+        assert_eq!(itembased_cf.num_users, retrained_cf.num_users);
+        assert_eq!(itembased_cf.user_histories, retrained_cf.user_histories);
+
+        // Interaction-level updates must produce the same model as whole histories
+        let mut history_cf = ItembasedCF::new(4, 2);
+        history_cf.partial_fit(&[(0, vec![0, 2]), (1, vec![0, 2]), (2, vec![1, 2])]);
+
+        assert_eq!(itembased_cf.c, history_cf.c);
+        assert_eq!(itembased_cf.s, history_cf.s);
+        assert_eq!(itembased_cf.n, history_cf.n);
+        assert_eq!(itembased_cf.num_users, history_cf.num_users);
+
+        itembased_cf.forget_user(2);
+        history_cf.forget(&(2, vec![1, 2]));
+
+        assert_eq!(itembased_cf.c, history_cf.c);
+        assert_eq!(itembased_cf.s, history_cf.s);
+        assert!(!itembased_cf.user_histories.contains_key(&2));
+        assert!(!itembased_cf.user_histories.contains_key(&3));
+    }
+
+    #[test]
+    fn catalog_grows_and_retires_items() {
+        let mut itembased_cf = ItembasedCF::new(0, 2);
+        itembased_cf.track_retired_items();
+
+        itembased_cf.partial_fit(&[(0, vec![0, 3]), (1, vec![3, 5])]);
+        assert_eq!(itembased_cf.num_items(), 6);
+
+        itembased_cf.partial_fit_interactions(&[(7, 5), (7, 9)]);
+        assert_eq!(itembased_cf.num_items(), 10);
+        assert!(itembased_cf.predict(&9).contains(&5));
+
+        // Unknown items have no neighbours
+        assert!(itembased_cf.predict(&42).is_empty());
+
+        itembased_cf.forget(&(0, vec![0, 3]));
+        itembased_cf.forget_interaction(7, 9);
+        assert_eq!(itembased_cf.take_retired_items(), vec![0, 9]);
+        assert!(itembased_cf.take_retired_items().is_empty());
+
+        // Retired ids can be reused
+        itembased_cf.partial_fit(&[(2, vec![9, 3])]);
+        itembased_cf.forget(&(2, vec![9, 3]));
+        itembased_cf.partial_fit(&[(3, vec![9, 5])]);
+        assert!(itembased_cf.take_retired_items().is_empty());
+
+        let mut retrained_cf = ItembasedCF::new(10, 2);
+        retrained_cf.partial_fit(&[(1, vec![3, 5]), (3, vec![9, 5])]);
+        retrained_cf.partial_fit_interactions(&[(7, 5)]);
+
+        assert_eq!(itembased_cf.c, retrained_cf.c);
+        assert_eq!(itembased_cf.s, retrained_cf.s);
+        assert_eq!(itembased_cf.n, retrained_cf.n);
+    }
+
+    #[test]
+    fn retired_items_are_only_recorded_on_request() {
+        let mut itembased_cf = ItembasedCF::new(0, 2);
+        itembased_cf.partial_fit(&[(0, vec![0, 3]), (1, vec![3, 5])]);
+        itembased_cf.forget(&(0, vec![0, 3]));
+
+        // Nothing about the forgotten item remains, like in a retrained model
+        assert_eq!(itembased_cf.retired_items, None);
+        assert!(itembased_cf.take_retired_items().is_empty());
+
+        itembased_cf.track_retired_items();
+        itembased_cf.forget(&(1, vec![3, 5]));
+        assert_eq!(itembased_cf.take_retired_items(), vec![3, 5]);
+        assert_eq!(itembased_cf.retired_items, Some(Vec::new()));
+    }
+
+    #[test]
+    fn forgetting_moves_users_back_below_the_cap() {
+        for policy in &[CapPolicy::Drop, CapPolicy::Truncate, CapPolicy::Sample] {
+            let user_cap = UserCap::new(3, *policy);
+
+            let mut itembased_cf = ItembasedCF::with_user_cap(6, 2, Similarity::Jaccard, user_cap);
+            itembased_cf.partial_fit_interactions(&[
+                (0, 0), (0, 1), (0, 2), (0, 3), (0, 4),
+                (1, 1), (1, 2),
+            ]);
+
+            // Capped histories are applied to all interactions of the user
+            let mut capped_cf = ItembasedCF::new(6, 2);
+            capped_cf.partial_fit_interactions(&[(1, 1), (1, 2)]);
+            for item in user_cap.apply(0, &[0, 1, 2, 3, 4]).iter() {
+                capped_cf.partial_fit_interactions(&[(0, *item)]);
+            }
+
+            assert_eq!(itembased_cf.c, capped_cf.c);
+            assert_eq!(itembased_cf.n, capped_cf.n);
+            assert_eq!(itembased_cf.num_users, capped_cf.num_users);
+
+            itembased_cf.forget_interaction(0, 4);
+            itembased_cf.forget_interaction(0, 1);
+
+            let mut retrained_cf = ItembasedCF::new(6, 2);
+            retrained_cf.partial_fit_interactions(&[(0, 0), (0, 2), (0, 3), (1, 1), (1, 2)]);
+
+            assert_eq!(itembased_cf.c, retrained_cf.c);
+            assert_eq!(itembased_cf.s, retrained_cf.s);
+            assert_eq!(itembased_cf.n, retrained_cf.n);
+            assert_eq!(itembased_cf.num_users, retrained_cf.num_users);
+
+            itembased_cf.forget_user(0);
+            itembased_cf.forget_user(1);
+            assert!(itembased_cf.c.iter().all(|cooccurrences| cooccurrences.is_empty()));
+            assert!(itembased_cf.n.iter().all(|count| *count == 0));
+            assert_eq!(itembased_cf.num_users, 0);
+        }
+    }
+
+    #[test]
+    fn user_cap_for_histories() {
+        let user_cap = UserCap::new(2, CapPolicy::Truncate);
+        let mut itembased_cf = ItembasedCF::with_user_cap(4, 2, Similarity::Jaccard, user_cap);
+
+        itembased_cf.partial_fit(&[(0, vec![3, 1, 2]), (1, vec![0, 3])]);
+
+        let mut retrained_cf = ItembasedCF::new(4, 2);
+        retrained_cf.partial_fit(&[(0, vec![1, 2]), (1, vec![0, 3])]);
+
+        assert_eq!(itembased_cf.c, retrained_cf.c);
+        assert_eq!(itembased_cf.n, retrained_cf.n);
+
+        itembased_cf.forget(&(0, vec![3, 1, 2]));
+        retrained_cf.forget(&(0, vec![1, 2]));
+
+        assert_eq!(itembased_cf.c, retrained_cf.c);
+        assert_eq!(itembased_cf.s, retrained_cf.s);
+    }
+
+    #[test]
+    fn sampled_histories_depend_on_the_user() {
+        let user_cap = UserCap::new(3, CapPolicy::Sample);
+        let histories: Vec<(u32, Vec<u32>)> = (0..20)
+            .map(|user| (user, (0..8).collect()))
+            .collect();
+
+        // The sample is keyed by user and item, so the same history gives different samples
+        let distinct_samples: FnvHashSet<Vec<u32>> = histories.iter()
+            .map(|(user, history)| user_cap.apply(*user, history).into_owned())
+            .collect();
+        assert!(distinct_samples.len() > 1);
+
+        let interactions: Vec<(u32, u32)> = histories.iter()
+            .flat_map(|(user, history)| history.iter().map(move |item| (*user, *item)))
+            .collect();
+
+        let mut itembased_cf = ItembasedCF::with_user_cap(8, 2, Similarity::Jaccard, user_cap);
+        itembased_cf.partial_fit(&histories);
+
+        let mut parallel_cf = ItembasedCF::with_user_cap(8, 2, Similarity::Jaccard, user_cap);
+        parallel_cf.partial_fit_parallel(&histories, 3);
+
+        let mut interactions_cf = ItembasedCF::with_user_cap(8, 2, Similarity::Jaccard, user_cap);
+        interactions_cf.partial_fit_interactions(&interactions);
+
+        for other_cf in &[&parallel_cf, &interactions_cf] {
+            assert_eq!(itembased_cf.c, other_cf.c);
+            assert_eq!(itembased_cf.s, other_cf.s);
+            assert_eq!(itembased_cf.n, other_cf.n);
+        }
+
+        for (user, history) in histories.iter().take(10) {
+            itembased_cf.forget(&(*user, history.clone()));
+            interactions_cf.forget_user(*user);
+        }
+
+        assert_eq!(itembased_cf.c, interactions_cf.c);
+        assert_eq!(itembased_cf.s, interactions_cf.s);
+        assert_eq!(itembased_cf.n, interactions_cf.n);
+    }
+
+    #[test]
+    fn parallel_partial_fit_matches_sequential() {
+        let similarities = [Similarity::Jaccard, Similarity::ConditionalProbability,
+            Similarity::LogLikelihoodRatio];
+
+        let num_items = 20;
+
+        for similarity in similarities.iter() {
+            for seed in 0..5 {
+                let mut rng = StdRng::seed_from_u64(seed);
+
+                let histories: Vec<(u32, Vec<u32>)> = (0..50)
+                    .map(|user| {
+                        let history_length = rng.gen_range(0, 8);
+                        let history = (0..history_length)
+                            .map(|_| rng.gen_range(0, num_items as u32))
+                            .collect();
+                        (user, history)
+                    })
+                    .collect();
+
+                let (first_batch, second_batch) = histories.split_at(30);
+
+                for num_threads in &[1, 3, 8] {
+                    let mut sequential_cf = ItembasedCF::with_similarity(5, 3, *similarity);
+                    sequential_cf.partial_fit(first_batch);
+                    sequential_cf.partial_fit(second_batch);
+
+                    let mut parallel_cf = ItembasedCF::with_similarity(5, 3, *similarity);
+                    parallel_cf.partial_fit_parallel(first_batch, *num_threads);
+                    parallel_cf.partial_fit_parallel(second_batch, *num_threads);
+
+                    assert_eq!(parallel_cf.c, sequential_cf.c);
+                    assert_eq!(parallel_cf.s, sequential_cf.s);
+                    assert_eq!(parallel_cf.n, sequential_cf.n);
+                    assert_eq!(parallel_cf.num_users, sequential_cf.num_users);
+
+                    assert_eq!(parallel_cf.neighbours, sequential_cf.neighbours);
+
+                    parallel_cf.forget(&first_batch[0]);
+                    sequential_cf.forget(&first_batch[0]);
+                    assert_eq!(parallel_cf.s, sequential_cf.s);
+                    assert_eq!(parallel_cf.neighbours, sequential_cf.neighbours);
+                }
+            }
+        }
+    }
+
+    fn assert_neighbours_match_similarities(itembased_cf: &ItembasedCF) {
+        let neighbours_per_item = itembased_cf.neighbours.iter();
+        for (similarities, neighbours) in itembased_cf.s.iter().zip(neighbours_per_item) {
+            assert_eq!(*neighbours, top_k(similarities, itembased_cf.k));
+        }
+        assert!(itembased_cf.stale_neighbours.is_empty());
+    }
+
+    #[test]
+    fn maintained_neighbours_match_recomputed_top_k() {
+        let similarities = [Similarity::Jaccard, Similarity::ConditionalProbability,
+            Similarity::PointwiseMutualInformation];
+
+        let num_users = 15;
+        let num_items = 12;
+
+        for similarity in similarities.iter() {
+            for seed in 0..20 {
+                let mut rng = StdRng::seed_from_u64(seed);
+                let mut itembased_cf = ItembasedCF::with_similarity(num_items, 2, *similarity);
+
+                for _ in 0..100 {
+                    let user = rng.gen_range(0, num_users);
+                    match rng.gen_range(0, 10) {
+                        0 => itembased_cf.forget_user(user),
+                        1..=3 => {
+                            let history = itembased_cf.user_histories.get(&user).cloned();
+                            if let Some(history) = history {
+                                let item = *history.choose(&mut rng).unwrap();
+                                itembased_cf.forget_interaction(user, item);
+                            }
+                        },
+                        _ => {
+                            let item = rng.gen_range(0, num_items as u32);
+                            itembased_cf.partial_fit_interactions(&[(user, item)]);
+                        },
+                    }
+
+                    assert_neighbours_match_similarities(&itembased_cf);
+                }
+            }
+        }
+    }
+}
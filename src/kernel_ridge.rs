@@ -0,0 +1,231 @@
+use ndarray::{Array1, Array2};
+
+use crate::IncrementalDecrementalModel;
+use crate::ridge::Example;
+
+/// Kernel functions for `KernelRidgeRegression`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kernel {
+    /// The inner product x'y, which gives the same model as ridge regression through the origin
+    Linear,
+    /// The radial basis function exp(-γ|x - y|²)
+    Rbf { gamma: f64 },
+}
+
+impl Kernel {
+    pub fn apply(&self, a: &Array1<f64>, b: &Array1<f64>) -> f64 {
+        match self {
+            Kernel::Linear => a.dot(b),
+            Kernel::Rbf { gamma } => {
+                let difference = a - b;
+                (-gamma * difference.dot(&difference)).exp()
+            }
+        }
+    }
+}
+
+/// Weighted kernel ridge regression for one or several targets, which maintains the inverse of
+/// K + λW⁻¹ for the kernel matrix K of the training examples and the diagonal matrix W of their
+/// weights. Adding an example grows the inverse by a row and a column via the Schur complement of
+/// its diagonal entry, forgetting an example removes them again via block-matrix inverse
+/// downdating. Both cost O(n²), the memory grows quadratically with the number of examples.
+#[derive(Debug)]
+pub struct KernelRidgeRegression {
+    kernel: Kernel,
+    lambda: f64,
+    examples: Vec<Example>,
+    inverse: Array2<f64>,
+    dual_weights: Array2<f64>,
+}
+
+impl KernelRidgeRegression {
+
+    pub fn new(kernel: Kernel, lambda: f64, num_targets: usize) -> Self {
+        assert!(lambda > 0.0, "The regularization strength must be positive");
+
+        KernelRidgeRegression {
+            kernel,
+            lambda,
+            examples: Vec::new(),
+            inverse: Array2::zeros((0, 0)),
+            dual_weights: Array2::zeros((0, num_targets)),
+        }
+    }
+
+    pub fn num_examples(&self) -> usize {
+        self.examples.len()
+    }
+
+    /// The weights of the training examples in the prediction, one column per target
+    pub fn dual_weights(&self) -> &Array2<f64> {
+        &self.dual_weights
+    }
+
+    fn kernel_row(&self, features: &Array1<f64>) -> Array1<f64> {
+        self.examples.iter()
+            .map(|example| self.kernel.apply(&example.features, features))
+            .collect()
+    }
+
+    /// Inverse of the block matrix [[A, k], [k', d]] from the inverse of A, see Petersen and
+    /// Pedersen, "The Matrix Cookbook", section 9.1.3
+    fn add(&mut self, example: &Example) {
+        let num_examples = self.examples.len();
+
+        let kernel_row = self.kernel_row(&example.features);
+        let diagonal = self.kernel.apply(&example.features, &example.features)
+            + self.lambda / example.weight;
+
+        let inverse_row = self.inverse.dot(&kernel_row);
+        let schur_complement = diagonal - kernel_row.dot(&inverse_row);
+
+        let previous_inverse = &self.inverse;
+        let inverse = Array2::from_shape_fn((num_examples + 1, num_examples + 1),
+            |(row, column)| {
+                match (row < num_examples, column < num_examples) {
+                    (true, true) => previous_inverse[[row, column]]
+                        + inverse_row[row] * inverse_row[column] / schur_complement,
+                    (true, false) => -inverse_row[row] / schur_complement,
+                    (false, true) => -inverse_row[column] / schur_complement,
+                    (false, false) => 1.0 / schur_complement,
+                }
+            });
+
+        self.inverse = inverse;
+        self.examples.push(example.clone());
+    }
+
+    /// Removes the row and column of an example from the inverse. For the block matrix inverse
+    /// [[A, k], [k', d]]⁻¹ = [[E, f], [f', g]], the inverse of the remaining block is E - ff'/g.
+    fn remove(&mut self, index: usize) {
+        let num_examples = self.examples.len();
+
+        let pivot = self.inverse[[index, index]];
+        let pivot_column = self.inverse.column(index).to_owned();
+        let remaining: Vec<usize> = (0..num_examples).filter(|other| *other != index).collect();
+
+        let previous_inverse = &self.inverse;
+        let inverse = Array2::from_shape_fn((num_examples - 1, num_examples - 1),
+            |(row, column)| {
+                let (row, column) = (remaining[row], remaining[column]);
+                previous_inverse[[row, column]] - pivot_column[row] * pivot_column[column] / pivot
+            });
+
+        self.inverse = inverse;
+        self.examples.remove(index);
+    }
+
+    fn solve(&mut self) {
+        let num_targets = self.dual_weights.cols();
+        let targets = Array2::from_shape_fn((self.examples.len(), num_targets), |(row, column)| {
+            self.examples[row].targets[column]
+        });
+        self.dual_weights = self.inverse.dot(&targets);
+    }
+}
+
+impl IncrementalDecrementalModel<Example, Array1<f64>, Array1<f64>> for KernelRidgeRegression {
+
+    fn partial_fit(&mut self, data: &[Example]) {
+        for example in data.iter() {
+            self.add(example);
+        }
+        self.solve();
+    }
+
+    /// Forgets an example, which must have the same weight as when it was added
+    fn forget(&mut self, example: &Example) {
+        if let Some(index) = self.examples.iter().position(|other| other == example) {
+            self.remove(index);
+            self.solve();
+        }
+    }
+
+    /// Predicts all targets
+    fn predict(&self, features: &Array1<f64>) -> Array1<f64> {
+        self.kernel_row(features).dot(&self.dual_weights)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use ndarray::{Array1, Array2};
+
+    use crate::IncrementalDecrementalModel;
+    use crate::kernel_ridge::{Kernel, KernelRidgeRegression};
+    use crate::ridge::{Example, Preprocessing, RidgeRegression};
+
+    fn examples() -> Vec<Example> {
+        let rows = [
+            ([0.130010, -0.223675], [0.475747, -1.2], 1.0),
+            ([-0.504190, -0.223675], [-0.084074, 0.3], 2.0),
+            ([0.502476, -0.223675], [0.228626, 0.8], 0.5),
+            ([-0.735723, -1.537767], [-0.867025, -0.4], 1.0),
+            ([1.257476, 1.090417], [1.595389, 2.1], 1.5),
+        ];
+
+        rows.iter()
+            .map(|(features, targets, weight)| {
+                let features = Array1::from_vec(features.to_vec());
+                Example::with_weight(features, Array1::from_vec(targets.to_vec()), *weight)
+            })
+            .collect()
+    }
+
+    fn assert_same_predictions(
+        model: &KernelRidgeRegression,
+        other_model: &dyn IncrementalDecrementalModel<Example, Array1<f64>, Array1<f64>>)
+    {
+        for example in examples().iter() {
+            let predictions = model.predict(&example.features);
+            let other_predictions = other_model.predict(&example.features);
+            for (prediction, other_prediction) in predictions.iter().zip(other_predictions.iter()) {
+                assert!((prediction - other_prediction).abs() < 0.000001);
+            }
+        }
+    }
+
+    #[test]
+    fn linear_kernel_matches_ridge_regression() {
+        let examples = examples();
+
+        let mut kernel_ridge = KernelRidgeRegression::new(Kernel::Linear, 0.1, 2);
+        kernel_ridge.partial_fit(&examples);
+        kernel_ridge.forget(&examples[1]);
+        assert_eq!(kernel_ridge.num_examples(), 4);
+
+        let remaining: Vec<Example> = examples.iter().enumerate()
+            .filter(|(index, _example)| *index != 1)
+            .map(|(_index, example)| example.clone())
+            .collect();
+
+        let x = Array2::from_shape_fn((4, 2), |(row, column)| remaining[row].features[column]);
+        let y = Array2::from_shape_fn((4, 2), |(row, column)| remaining[row].targets[column]);
+        let sample_weights = remaining.iter().map(|example| example.weight).collect();
+
+        let ridge = RidgeRegression::with_sample_weights(
+            x, y, 0.1, Preprocessing::None, 1, sample_weights);
+
+        assert_same_predictions(&kernel_ridge, &ridge);
+    }
+
+    #[test]
+    fn forgetting_matches_retraining() {
+        let examples = examples();
+        let kernel = Kernel::Rbf { gamma: 0.5 };
+
+        let mut kernel_ridge = KernelRidgeRegression::new(kernel, 0.1, 2);
+        kernel_ridge.partial_fit(&examples);
+        kernel_ridge.forget(&examples[4]);
+        kernel_ridge.forget(&examples[0]);
+
+        let mut retrained = KernelRidgeRegression::new(kernel, 0.1, 2);
+        retrained.partial_fit(&examples[1..4]);
+
+        assert_same_predictions(&kernel_ridge, &retrained);
+        for (value, other_value) in kernel_ridge.inverse.iter().zip(retrained.inverse.iter()) {
+            assert!((value - other_value).abs() < 0.000001);
+        }
+    }
+}
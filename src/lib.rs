@@ -4,10 +4,21 @@ extern crate ndarray;
 extern crate abomonation_derive;
 extern crate abomonation;
 
+pub mod als;
+pub mod catalog;
+pub mod cholesky;
+pub mod cross_validation;
 pub mod itembased;
+pub mod kernel_ridge;
 pub mod lsh;
+pub mod markov;
+pub mod pca;
 pub mod ridge;
 pub mod mnb;
+pub mod similarity;
+pub mod user_cap;
+pub mod userbased;
+pub mod weighted_itembased;
 
 pub mod io_utils;
 
@@ -0,0 +1,218 @@
+use std::cmp::Ordering;
+
+use fnv::FnvHashMap;
+
+use crate::IncrementalDecrementalModel;
+
+/// First-order Markov chain for "next item" recommendations. We count how often users moved from
+/// one item to another in their ordered sessions and rank the next items of an item by these
+/// transition counts. Consecutive repetitions of the same item are not counted as transitions.
+/// Forgetting a session decrements exactly the transitions it contributed.
+#[derive(Debug)]
+pub struct MarkovChain {
+    k: usize,
+    transitions: Vec<FnvHashMap<u32, u32>>,
+    num_outgoing: Vec<u32>,
+}
+
+impl MarkovChain {
+
+    pub fn new(num_items: usize, k: usize) -> Self {
+        MarkovChain {
+            k,
+            transitions: vec![FnvHashMap::with_capacity_and_hasher(0, Default::default()); num_items],
+            num_outgoing: vec![0; num_items],
+        }
+    }
+
+    /// Estimated probability that a user moves on to `next_item` after `item`
+    pub fn transition_probability(&self, item: u32, next_item: u32) -> f64 {
+        match self.transitions.get(item as usize).and_then(|next| next.get(&next_item)) {
+            Some(count) => *count as f64 / self.num_outgoing[item as usize] as f64,
+            None => 0.0,
+        }
+    }
+
+    /// The `n` most likely next items after `item` with their transition probabilities, ties are
+    /// broken in favor of the smaller item id
+    pub fn recommend_next(&self, item: u32, n: usize) -> Vec<(u32, f64)> {
+        let next_items = match self.transitions.get(item as usize) {
+            Some(next_items) => next_items,
+            None => return Vec::new(),
+        };
+
+        let mut ranked: Vec<(u32, u32)> = next_items.iter()
+            .map(|(next_item, count)| (*next_item, *count))
+            .collect();
+
+        ranked.sort_by(|(item_a, count_a), (item_b, count_b)| {
+            match count_b.cmp(count_a) {
+                Ordering::Equal => item_a.cmp(item_b),
+                ordering => ordering,
+            }
+        });
+        ranked.truncate(n);
+
+        let num_outgoing = self.num_outgoing[item as usize] as f64;
+        ranked.into_iter()
+            .map(|(next_item, count)| (next_item, count as f64 / num_outgoing))
+            .collect()
+    }
+
+    /// All transitions with their counts, sorted by transition, to compare the model with the
+    /// differential implementation
+    #[cfg(test)]
+    pub(crate) fn transition_counts(&self) -> Vec<((u32, u32), u32)> {
+        let mut transition_counts: Vec<((u32, u32), u32)> = self.transitions.iter().enumerate()
+            .flat_map(|(item, next_items)| {
+                next_items.iter()
+                    .map(move |(next_item, count)| ((item as u32, *next_item), *count))
+            })
+            .collect();
+
+        transition_counts.sort();
+        transition_counts
+    }
+
+    fn ensure_item(&mut self, item: u32) {
+        let num_items = item as usize + 1;
+        if num_items > self.num_outgoing.len() {
+            self.transitions.resize(num_items,
+                FnvHashMap::with_capacity_and_hasher(0, Default::default()));
+            self.num_outgoing.resize(num_items, 0);
+        }
+    }
+}
+
+/// The transitions between consecutive items of a session
+fn transitions(session: &[u32]) -> impl Iterator<Item=(u32, u32)> + '_ {
+    session.windows(2)
+        .map(|pair| (pair[0], pair[1]))
+        .filter(|(item, next_item)| item != next_item)
+}
+
+impl IncrementalDecrementalModel<Vec<u32>, u32, Vec<u32>> for MarkovChain {
+
+    fn partial_fit(&mut self, sessions: &[Vec<u32>]) {
+        for session in sessions.iter() {
+            for (item, next_item) in transitions(session) {
+                self.ensure_item(std::cmp::max(item, next_item));
+
+                *self.transitions[item as usize].entry(next_item).or_insert(0) += 1;
+                self.num_outgoing[item as usize] += 1;
+            }
+        }
+    }
+
+    /// Forgets the transitions of a session, unknown transitions are ignored
+    fn forget(&mut self, session: &Vec<u32>) {
+        for (item, next_item) in transitions(session) {
+            let next_items = match self.transitions.get_mut(item as usize) {
+                Some(next_items) => next_items,
+                None => continue,
+            };
+
+            let count = match next_items.get_mut(&next_item) {
+                Some(count) => count,
+                None => continue,
+            };
+            *count -= 1;
+
+            // Drop transitions which did not happen anymore
+            if *count == 0 {
+                next_items.remove(&next_item);
+                if next_items.len() * 4 < next_items.capacity() {
+                    next_items.shrink_to_fit();
+                }
+            }
+
+            self.num_outgoing[item as usize] -= 1;
+        }
+    }
+
+    /// The k most likely next items, ordered by decreasing probability
+    fn predict(&self, item: &u32) -> Vec<u32> {
+        self.recommend_next(*item, self.k)
+            .into_iter()
+            .map(|(next_item, _probability)| next_item)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::StdRng;
+    use rand::seq::SliceRandom;
+
+    use crate::IncrementalDecrementalModel;
+    use crate::markov::MarkovChain;
+
+    #[test]
+    fn toy_example() {
+        let mut markov_chain = MarkovChain::new(4, 2);
+        markov_chain.partial_fit(&[vec![0, 1, 2], vec![0, 2, 2, 3], vec![3, 0, 1]]);
+
+        assert_eq!(markov_chain.transition_probability(0, 1), 2.0 / 3.0);
+        assert_eq!(markov_chain.transition_probability(2, 2), 0.0);
+        assert_eq!(markov_chain.predict(&0), vec![1, 2]);
+        assert_eq!(markov_chain.recommend_next(2, 5), vec![(3, 1.0)]);
+
+        markov_chain.forget(&vec![3, 0, 1]);
+
+        assert_eq!(markov_chain.predict(&0), vec![1, 2]);
+        assert_eq!(markov_chain.transition_probability(0, 1), 0.5);
+        assert!(markov_chain.transitions[3].is_empty());
+        assert_eq!(markov_chain.num_outgoing[3], 0);
+    }
+
+    #[test]
+    fn forgetting_matches_retraining() {
+        let num_items = 6;
+
+        for seed in 0..20 {
+            let mut rng = StdRng::seed_from_u64(seed);
+
+            let mut sessions: Vec<Vec<u32>> = (0..15)
+                .map(|_| {
+                    let length = rng.gen_range(0, 8);
+                    (0..length).map(|_| rng.gen_range(0, num_items)).collect()
+                })
+                .collect();
+
+            let mut markov_chain = MarkovChain::new(num_items as usize, 3);
+            markov_chain.partial_fit(&sessions);
+
+            sessions.shuffle(&mut rng);
+            let num_to_forget = rng.gen_range(1, sessions.len());
+            for session in sessions.drain(..num_to_forget) {
+                markov_chain.forget(&session);
+            }
+
+            let mut retrained = MarkovChain::new(num_items as usize, 3);
+            retrained.partial_fit(&sessions);
+
+            assert_eq!(markov_chain.transitions, retrained.transitions);
+            assert_eq!(markov_chain.num_outgoing, retrained.num_outgoing);
+        }
+    }
+
+    #[test]
+    fn unknown_transitions_are_ignored() {
+        let mut markov_chain = MarkovChain::new(4, 2);
+        markov_chain.partial_fit(&[vec![0, 1, 2], vec![2, 3]]);
+
+        // Neither the transitions nor the items are known
+        markov_chain.forget(&vec![1, 0]);
+        markov_chain.forget(&vec![7, 8]);
+        // Only the transition from 2 to 3 is known
+        markov_chain.forget(&vec![3, 2, 3]);
+
+        let mut retrained = MarkovChain::new(4, 2);
+        retrained.partial_fit(&[vec![0, 1, 2]]);
+
+        assert_eq!(markov_chain.transitions, retrained.transitions);
+        assert_eq!(markov_chain.num_outgoing, retrained.num_outgoing);
+    }
+}
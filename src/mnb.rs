@@ -1,22 +1,108 @@
 use fnv::FnvHashMap;
 use crate::IncrementalDecrementalModel;
 
+/// Memory layout for the per-label feature counts
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CountsLayout {
+    /// One array of length `num_features` per label, for dense feature indices `0..num_features`
+    Dense,
+    /// One hash map per label, for very sparse vocabularies where most counts stay zero
+    Sparse,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum FeatureCounts {
+    Dense(Vec<u32>),
+    Sparse(FnvHashMap<u32, u32>),
+}
+
+impl FeatureCounts {
+
+    fn new(layout: CountsLayout, num_features: u32) -> Self {
+        match layout {
+            CountsLayout::Dense => FeatureCounts::Dense(vec![0; num_features as usize]),
+            CountsLayout::Sparse => FeatureCounts::Sparse(FnvHashMap::default()),
+        }
+    }
+
+    /// Dense counts only hold the features `0..num_features`, we reject other indices instead of
+    /// growing the counts, as the grown counts would outlive forgetting the example
+    fn increment(&mut self, feature_index: u32, count: u32) {
+        match self {
+            FeatureCounts::Dense(counts) => {
+                assert!((feature_index as usize) < counts.len(),
+                    "Feature index {} out of range for the dense layout with {} features, use the \
+                     sparse layout for unbounded feature indices", feature_index, counts.len());
+                counts[feature_index as usize] += count
+            },
+            FeatureCounts::Sparse(counts) => *counts.entry(feature_index).or_insert(0) += count,
+        }
+    }
+
+    fn decrement(&mut self, feature_index: u32, count: u32) {
+        match self {
+            FeatureCounts::Dense(counts) => {
+                *counts.get_mut(feature_index as usize).expect("Unknown feature") -= count
+            },
+            FeatureCounts::Sparse(counts) => {
+                let remaining = {
+                    let nci = counts.get_mut(&feature_index).expect("Unknown feature");
+                    *nci -= count;
+                    *nci
+                };
+                // Drop features without any remaining count
+                if remaining == 0 {
+                    counts.remove(&feature_index);
+                    if counts.len() * 4 < counts.capacity() {
+                        counts.shrink_to_fit();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Sum of `count * ln(n_ci + 1)` over the features of an example, unseen features have a count
+    /// of zero in both layouts
+    fn log_likelihood(&self, features: &FnvHashMap<u32, u32>) -> f64 {
+        match self {
+            FeatureCounts::Dense(counts) => {
+                features.iter()
+                    .map(|(feature_index, count)| {
+                        let nci = counts.get(*feature_index as usize).cloned().unwrap_or(0);
+                        *count as f64 * ((nci + 1) as f64).ln()
+                    })
+                    .sum()
+            },
+            FeatureCounts::Sparse(counts) => {
+                features.iter()
+                    .map(|(feature_index, count)| {
+                        let nci = counts.get(feature_index).cloned().unwrap_or(0);
+                        *count as f64 * ((nci + 1) as f64).ln()
+                    })
+                    .sum()
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
 pub struct MultinomialNaiveBayes {
     num_labels: u8,
     num_features: u32,
-    feature_counts_per_label: Vec<FnvHashMap<u32, u32>>,
+    feature_counts_per_label: Vec<FeatureCounts>,
     counts_per_label: Vec<u32>,
 }
 
 impl MultinomialNaiveBayes {
 
     pub fn new(num_labels: u8, num_features: u32) -> Self {
-        let mut feature_counts_per_label = Vec::with_capacity(num_labels as usize);
-        for _ in 0..num_labels {
-            let counts = FnvHashMap::with_capacity_and_hasher(
-                num_features as usize, Default::default());
-            feature_counts_per_label.push(counts);
-        }
+        MultinomialNaiveBayes::with_layout(num_labels, num_features, CountsLayout::Dense)
+    }
+
+    pub fn with_layout(num_labels: u8, num_features: u32, layout: CountsLayout) -> Self {
+        let feature_counts_per_label = (0..num_labels)
+            .map(|_| FeatureCounts::new(layout, num_features))
+            .collect();
 
         let counts_per_label = vec![0; num_labels as usize];
 
@@ -47,8 +133,7 @@ impl IncrementalDecrementalModel<(MNBFeatures, u8), MNBFeatures, u8> for Multino
             let (features, label) = example;
             let label_index = *label as usize;
             for (feature_index, count) in features.features.iter() {
-                *self.feature_counts_per_label[label_index]
-                    .entry(*feature_index).or_insert(0) += *count;
+                self.feature_counts_per_label[label_index].increment(*feature_index, *count);
                 self.counts_per_label[label_index] += *count;
             }
         }
@@ -58,7 +143,7 @@ impl IncrementalDecrementalModel<(MNBFeatures, u8), MNBFeatures, u8> for Multino
         let (features, label) = data;
         let label_index = *label as usize;
         for (feature_index, count) in features.features.iter() {
-            *self.feature_counts_per_label[label_index].get_mut(feature_index).unwrap() -= *count;
+            self.feature_counts_per_label[label_index].decrement(*feature_index, *count);
             self.counts_per_label[label_index] -= *count;
         }
     }
@@ -68,14 +153,17 @@ impl IncrementalDecrementalModel<(MNBFeatures, u8), MNBFeatures, u8> for Multino
         let mut predicted_label: Option<u8> = None;
         let mut predicted_label_log_prob: Option<f64> = None;
 
+        // The denominator (n_c + num_features) is shared by all features of a label, so we
+        // factor it out of the sum and only need one logarithm per feature and label
+        let total_count: u32 = data.features.values().sum();
+
         for label in 0..self.num_labels {
-            let mut log_prob = 0.0;
             let label_index = label as usize;
             let nc = self.counts_per_label[label_index];
-            for (feature_index, count) in data.features.iter() {
-                let nci = self.feature_counts_per_label[label_index].get(feature_index).unwrap();
-                log_prob += *count as f64 * ((nci + 1) as f64 / (nc + self.num_features) as f64).ln()
-            }
+            let log_denominator = ((nc + self.num_features) as f64).ln();
+
+            let log_prob = self.feature_counts_per_label[label_index].log_likelihood(&data.features)
+                - total_count as f64 * log_denominator;
 
             match predicted_label_log_prob {
                 None => {
@@ -94,4 +182,145 @@ impl IncrementalDecrementalModel<(MNBFeatures, u8), MNBFeatures, u8> for Multino
 
         predicted_label.unwrap()
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::cmp::max;
+
+    use fnv::{FnvHashMap, FnvHashSet};
+
+    use crate::IncrementalDecrementalModel;
+    use crate::mnb::{MultinomialNaiveBayes, MNBFeatures, CountsLayout, FeatureCounts};
+
+    fn features(pairs: &[(u32, u32)]) -> MNBFeatures {
+        let features: FnvHashMap<u32, u32> = pairs.iter().cloned().collect();
+        MNBFeatures::new(features)
+    }
+
+    #[test]
+    fn dense_and_sparse_layouts_agree() {
+        let examples = vec![
+            (features(&[(0, 2), (1, 1)]), 0),
+            (features(&[(0, 1), (3, 1)]), 0),
+            (features(&[(2, 3), (4, 1)]), 1),
+            (features(&[(3, 1), (4, 2)]), 1),
+        ];
+
+        let mut dense = MultinomialNaiveBayes::with_layout(2, 5, CountsLayout::Dense);
+        let mut sparse = MultinomialNaiveBayes::with_layout(2, 5, CountsLayout::Sparse);
+
+        dense.partial_fit(&examples);
+        sparse.partial_fit(&examples);
+
+        for query in &[features(&[(0, 1)]), features(&[(4, 1)]), features(&[(1, 1), (2, 1)])] {
+            assert_eq!(dense.predict(query), sparse.predict(query));
+        }
+
+        assert_eq!(dense.predict(&features(&[(0, 3), (1, 1)])), 0);
+        assert_eq!(sparse.predict(&features(&[(2, 1), (4, 1)])), 1);
+    }
+
+    #[test]
+    fn unseen_features_have_zero_counts() {
+        let examples = vec![
+            (features(&[(0, 2), (1, 1)]), 0),
+            (features(&[(2, 3), (1, 1)]), 1),
+        ];
+
+        let mut dense = MultinomialNaiveBayes::with_layout(2, 3, CountsLayout::Dense);
+        let mut sparse = MultinomialNaiveBayes::with_layout(2, 3, CountsLayout::Sparse);
+        dense.partial_fit(&examples);
+        sparse.partial_fit(&examples);
+
+        // Feature 7 is outside of the dense counts and was never seen by the sparse counts
+        let queries = [
+            features(&[(7, 2)]),
+            features(&[(0, 1), (7, 5)]),
+            features(&[(2, 1), (7, 1)]),
+        ];
+        for query in queries.iter() {
+            assert_eq!(dense.predict(query), sparse.predict(query));
+        }
+        assert_eq!(dense.predict(&features(&[(0, 1), (7, 5)])), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "out of range for the dense layout")]
+    fn dense_layout_rejects_out_of_range_features() {
+        let mut mnb = MultinomialNaiveBayes::with_layout(2, 3, CountsLayout::Dense);
+        mnb.partial_fit(&[(features(&[(3, 1)]), 0)]);
+    }
+
+    #[test]
+    fn forget_removes_zero_counts() {
+        let examples = vec![
+            (features(&[(0, 2), (1, 1)]), 0),
+            (features(&[(0, 1), (3, 1)]), 0),
+            (features(&[(2, 3), (4, 1)]), 1),
+        ];
+
+        for layout in &[CountsLayout::Dense, CountsLayout::Sparse] {
+            let mut mnb = MultinomialNaiveBayes::with_layout(2, 5, *layout);
+            mnb.partial_fit(&examples);
+            mnb.forget(&examples[1]);
+            mnb.forget(&examples[2]);
+
+            let mut retrained = MultinomialNaiveBayes::with_layout(2, 5, *layout);
+            retrained.partial_fit(&examples[..1]);
+
+            assert_eq!(mnb, retrained);
+            assert_same_keys(&mnb, &retrained);
+        }
+
+        let mut mnb = MultinomialNaiveBayes::with_layout(2, 5, CountsLayout::Sparse);
+        mnb.partial_fit(&examples);
+        mnb.forget(&examples[1]);
+
+        match &mnb.feature_counts_per_label[0] {
+            FeatureCounts::Sparse(counts) => {
+                assert!(!counts.contains_key(&3));
+                assert_eq!(counts.get(&0), Some(&2));
+            },
+            FeatureCounts::Dense(_) => panic!("Expected sparse counts"),
+        }
+    }
+
+    /// The derived equality only compares the entries, so we additionally check that the sparse
+    /// counts hold the same keys and no more capacity than needed after the shrink heuristic
+    fn assert_same_keys(mnb: &MultinomialNaiveBayes, other_mnb: &MultinomialNaiveBayes) {
+        let label_counts = mnb.feature_counts_per_label.iter()
+            .zip(other_mnb.feature_counts_per_label.iter());
+
+        for (counts, other_counts) in label_counts {
+            if let (FeatureCounts::Sparse(counts), FeatureCounts::Sparse(other_counts)) =
+                (counts, other_counts) {
+                let keys: FnvHashSet<u32> = counts.keys().cloned().collect();
+                let other_keys: FnvHashSet<u32> = other_counts.keys().cloned().collect();
+                assert_eq!(keys, other_keys);
+                assert_eq!(counts.len(), other_counts.len());
+                assert!(counts.capacity() <= max(4 * counts.len(), other_counts.capacity()));
+            }
+        }
+    }
+
+    #[test]
+    fn forget_shrinks_sparse_counts() {
+        let large_example = (features(&(0..64).map(|feature| (feature, 1)).collect::<Vec<_>>()), 0);
+        let small_example = (features(&[(0, 1)]), 0);
+
+        let mut mnb = MultinomialNaiveBayes::with_layout(1, 64, CountsLayout::Sparse);
+        mnb.partial_fit(&[large_example.clone(), small_example]);
+        mnb.forget(&large_example);
+
+        match &mnb.feature_counts_per_label[0] {
+            FeatureCounts::Sparse(counts) => {
+                assert_eq!(counts.len(), 1);
+                // Without shrinking, the map would keep the capacity for all 64 features
+                assert!(counts.capacity() <= 4);
+            },
+            FeatureCounts::Dense(_) => panic!("Expected sparse counts"),
+        }
+    }
+}
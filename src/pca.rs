@@ -0,0 +1,276 @@
+use ndarray::{Array1, Array2};
+use ndarray_linalg::{Eigh, UPLO};
+
+use crate::IncrementalDecrementalModel;
+
+/// Principal component analysis, which maintains the mean and the scatter matrix
+/// Σ (x - μ)(x - μ)' of the examples with Welford's algorithm, so that forgetting an example
+/// gives the same components as retraining without it. The components are the eigenvectors of
+/// the covariance matrix with the largest eigenvalues, we recompute them after every update in
+/// O(d³), independent of the number of examples.
+///
+/// The projections from `transform` change with the components, so models which are fitted on
+/// them, e.g., `ApproximateKnn` via `lsh::Example::new(pca.transform(&features), label)` or
+/// `RidgeRegression` via `transform_rows`, have to be fitted again after an update.
+#[derive(Debug)]
+pub struct PrincipalComponentAnalysis {
+    num_components: usize,
+    num_examples: usize,
+    mean: Array1<f64>,
+    scatter: Array2<f64>,
+    components: Array2<f64>,
+    explained_variance: Array1<f64>,
+}
+
+impl PrincipalComponentAnalysis {
+
+    pub fn new(num_features: usize, num_components: usize) -> Self {
+        assert!(num_components <= num_features,
+            "There cannot be more components than features");
+
+        PrincipalComponentAnalysis {
+            num_components,
+            num_examples: 0,
+            mean: Array1::zeros(num_features),
+            scatter: Array2::zeros((num_features, num_features)),
+            components: Array2::zeros((num_features, num_components)),
+            explained_variance: Array1::zeros(num_components),
+        }
+    }
+
+    pub fn num_examples(&self) -> usize {
+        self.num_examples
+    }
+
+    pub fn mean(&self) -> &Array1<f64> {
+        &self.mean
+    }
+
+    /// The sample covariance matrix of the examples
+    pub fn covariance(&self) -> Array2<f64> {
+        if self.num_examples > 1 {
+            &self.scatter / (self.num_examples - 1) as f64
+        } else {
+            Array2::zeros(self.scatter.dim())
+        }
+    }
+
+    /// The principal components as columns, ordered by decreasing explained variance
+    pub fn components(&self) -> &Array2<f64> {
+        &self.components
+    }
+
+    /// The variance of the examples along each of the components
+    pub fn explained_variance(&self) -> &Array1<f64> {
+        &self.explained_variance
+    }
+
+    /// Projects the centered features onto the principal components
+    pub fn transform(&self, features: &Array1<f64>) -> Array1<f64> {
+        (features - &self.mean).dot(&self.components)
+    }
+
+    /// Projects every row of `x` like `transform`
+    pub fn transform_rows(&self, x: &Array2<f64>) -> Array2<f64> {
+        (x - &self.mean).dot(&self.components)
+    }
+
+    /// Adds or removes a single example from the scatter matrix via a rank-one update, forgetting
+    /// from an empty model does nothing
+    fn update(&mut self, features: &Array1<f64>, forget: bool) {
+        let num_before = self.num_examples as f64;
+
+        let (coefficient, delta) = if !forget {
+            // Welford's update, based on the deviation from the mean before the update
+            let num_after = num_before + 1.0;
+            let delta = features - &self.mean;
+            self.mean.scaled_add(1.0 / num_after, &delta);
+
+            self.num_examples += 1;
+            (num_before / num_after, delta)
+        } else {
+            if self.num_examples == 0 {
+                return;
+            }
+
+            // Reverse of Welford's update, based on the deviation from the mean after the update
+            self.num_examples -= 1;
+            if self.num_examples == 0 {
+                // Avoid leftover rounding errors once all examples are forgotten
+                self.mean.fill(0.0);
+                self.scatter.fill(0.0);
+                return;
+            }
+
+            let num_after = num_before - 1.0;
+            self.mean = (num_before * &self.mean - features) / num_after;
+            (-num_after / num_before, features - &self.mean)
+        };
+
+        for ((row, column), value) in self.scatter.indexed_iter_mut() {
+            *value += coefficient * delta[row] * delta[column];
+        }
+    }
+
+    /// Recomputes the components from the eigendecomposition of the covariance matrix
+    fn decompose(&mut self) {
+        let (eigenvalues, eigenvectors) = self.covariance()
+            .eigh(UPLO::Upper)
+            .expect("Unable to decompose the covariance matrix");
+
+        let mut order: Vec<usize> = (0..eigenvalues.len()).collect();
+        order.sort_by(|a, b| eigenvalues[*b].total_cmp(&eigenvalues[*a]));
+
+        for (component, index) in order.into_iter().take(self.num_components).enumerate() {
+            let mut eigenvector = eigenvectors.column(index).to_owned();
+
+            // Eigenvectors are only unique up to their sign, we make the entry with the largest
+            // magnitude positive, so that the components do not flip between updates
+            let largest = eigenvector.iter().fold(0.0_f64, |largest, value| {
+                if value.abs() > largest.abs() { *value } else { largest }
+            });
+            if largest < 0.0 {
+                eigenvector.mapv_inplace(|value| -value);
+            }
+
+            self.components.column_mut(component).assign(&eigenvector);
+            // Rounding errors can make the eigenvalues of a singular matrix slightly negative
+            self.explained_variance[component] = eigenvalues[index].max(0.0);
+        }
+    }
+}
+
+impl IncrementalDecrementalModel<Array1<f64>, Array1<f64>, Array1<f64>>
+    for PrincipalComponentAnalysis {
+
+    fn partial_fit(&mut self, data: &[Array1<f64>]) {
+        for features in data.iter() {
+            self.update(features, false);
+        }
+        self.decompose();
+    }
+
+    fn forget(&mut self, features: &Array1<f64>) {
+        self.update(features, true);
+        self.decompose();
+    }
+
+    /// Projects the features like `transform`
+    fn predict(&self, features: &Array1<f64>) -> Array1<f64> {
+        self.transform(features)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use ndarray::{Array1, Array2};
+
+    use crate::IncrementalDecrementalModel;
+    use crate::pca::PrincipalComponentAnalysis;
+    use crate::ridge::{Example, Preprocessing, RidgeRegression};
+
+    fn examples() -> Vec<Array1<f64>> {
+        vec![
+            array![2.5, 2.4, 0.5],
+            array![0.5, 0.7, -0.3],
+            array![2.2, 2.9, 0.1],
+            array![1.9, 2.2, 0.8],
+            array![3.1, 3.0, -0.4],
+            array![2.3, 2.7, 0.3],
+            array![1.0, 1.1, 0.2],
+        ]
+    }
+
+    fn assert_close(values: &Array1<f64>, other_values: &Array1<f64>) {
+        for (value, other_value) in values.iter().zip(other_values.iter()) {
+            assert!((value - other_value).abs() < 0.000001);
+        }
+    }
+
+    #[test]
+    fn components_are_eigenvectors_of_the_covariance() {
+        let mut pca = PrincipalComponentAnalysis::new(3, 2);
+        pca.partial_fit(&examples());
+
+        let covariance = pca.covariance();
+        for component in 0..2 {
+            let eigenvector = pca.components().column(component).to_owned();
+            let variance = pca.explained_variance()[component];
+            assert_close(&covariance.dot(&eigenvector), &(variance * &eigenvector));
+        }
+
+        // The first two features are strongly correlated and dominate the variance
+        assert!(pca.explained_variance()[0] > pca.explained_variance()[1]);
+        assert!(pca.components()[[0, 0]] > 0.6 && pca.components()[[1, 0]] > 0.6);
+    }
+
+    #[test]
+    fn forgetting_matches_retraining() {
+        let examples = examples();
+
+        let mut pca = PrincipalComponentAnalysis::new(3, 2);
+        pca.partial_fit(&examples);
+        pca.forget(&examples[4]);
+        pca.forget(&examples[1]);
+
+        let remaining: Vec<Array1<f64>> = examples.iter().enumerate()
+            .filter(|(index, _features)| *index != 4 && *index != 1)
+            .map(|(_index, features)| features.clone())
+            .collect();
+
+        let mut retrained = PrincipalComponentAnalysis::new(3, 2);
+        retrained.partial_fit(&remaining);
+
+        assert_eq!(pca.num_examples(), retrained.num_examples());
+        assert_close(pca.mean(), retrained.mean());
+        assert_close(pca.explained_variance(), retrained.explained_variance());
+        for features in examples.iter() {
+            assert_close(&pca.predict(features), &retrained.predict(features));
+        }
+
+        for features in remaining.iter() {
+            pca.forget(features);
+        }
+        assert_eq!(pca.num_examples(), 0);
+        assert_close(pca.mean(), &Array1::zeros(3));
+        assert_eq!(pca.scatter, Array2::zeros((3, 3)));
+
+        pca.forget(&examples[0]);
+        assert_eq!(pca.num_examples(), 0);
+        assert_eq!(pca.scatter, Array2::zeros((3, 3)));
+    }
+
+    #[test]
+    fn projections_feed_ridge_regression() {
+        let examples = examples();
+        let targets = array![[1.2], [-0.3], [1.5], [0.9], [2.0], [1.4], [0.1]];
+
+        let mut pca = PrincipalComponentAnalysis::new(3, 3);
+        pca.partial_fit(&examples);
+
+        let x = Array2::from_shape_fn((7, 3), |(row, column)| examples[row][column]);
+        let projected_x = pca.transform_rows(&x);
+
+        // Projecting onto all components is a rotation, which does not change a ridge
+        // regression with an intercept
+        let mut ridge = RidgeRegression::with_preprocessing(
+            x, targets.clone(), 0.1, Preprocessing::Center);
+        let mut projected_ridge = RidgeRegression::with_preprocessing(
+            projected_x, targets.clone(), 0.1, Preprocessing::Center);
+
+        for features in examples.iter() {
+            let prediction = ridge.predict(features);
+            assert_close(&prediction, &projected_ridge.predict(&pca.transform(features)));
+        }
+
+        // Both models forget the same example, as long as the projection stays fixed
+        ridge.forget(&Example::new(examples[0].clone(), targets[[0, 0]]));
+        projected_ridge.forget(&Example::new(pca.transform(&examples[0]), targets[[0, 0]]));
+
+        for features in examples.iter() {
+            let prediction = ridge.predict(features);
+            assert_close(&prediction, &projected_ridge.predict(&pca.transform(features)));
+        }
+    }
+}
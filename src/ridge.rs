@@ -1,165 +1,1056 @@
-extern crate rgsl;
-
 use crate::IncrementalDecrementalModel;
+use crate::cholesky::Cholesky;
 
-use rgsl::MatrixF64;
-use rgsl::VectorF64;
-
-use rgsl::linear_algebra::{QR_decomp, QR_QRsolve, QR_update, QR_unpack};
-use rgsl::blas::level1::{daxpy, ddot};
-use rgsl::blas::level3::dgemm;
-use rgsl::blas::level2::dgemv;
-use rgsl::cblas::Transpose::{Trans,NoTrans};
+use ndarray::{Array1, Array2, Axis};
 
 
-#[derive(Debug)]
+/// An example with one or several targets and a positive weight, which scales its contribution
+/// to the squared loss
+#[derive(Debug, Clone, PartialEq)]
 pub struct Example {
-    pub features: VectorF64,
-    pub target: f64,
+    pub features: Array1<f64>,
+    pub targets: Array1<f64>,
+    pub weight: f64,
 }
 
 impl Example {
-    pub fn new(features: VectorF64, target: f64) -> Example {
-        Example { features, target }
+    pub fn new(features: Array1<f64>, target: f64) -> Example {
+        Example::with_targets(features, Array1::from_elem(1, target))
     }
-}
 
-impl Clone for Example {
-    fn clone(&self) -> Self {
-        let cloned_features = self.features.clone().expect("Unable to clone!");
-        Example::new(cloned_features, self.target)
+    pub fn with_targets(features: Array1<f64>, targets: Array1<f64>) -> Example {
+        Example::with_weight(features, targets, 1.0)
     }
+
+    pub fn with_weight(features: Array1<f64>, targets: Array1<f64>, weight: f64) -> Example {
+        assert!(weight > 0.0, "Example weights must be positive");
+        Example { features, targets, weight }
+    }
+}
+
+/// How features and targets are preprocessed before fitting the weights
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Preprocessing {
+    /// Fit through the origin, all weights are penalized
+    None,
+    /// Fit an unpenalized intercept by centering the features and targets with their means
+    Center,
+    /// Additionally scale the centered features to unit variance
+    Standardize,
 }
 
+/// Weighted ridge regression for one or several targets, which maintains the (centered) Gram
+/// matrix X'WX and X'WY, as well as the Cholesky decomposition of X'WX + λI, which is shared by
+/// all targets. The means used for centering are weighted running means, which are updated
+/// exactly when an example is added or forgotten (Welford's algorithm), so that forgetting an
+/// example with its original weight gives the same model as retraining without it.
+///
+/// Rank-one updates and especially downdates of the decomposition accumulate rounding errors,
+/// which are amplified by the condition number of X'X + λI. After every rank-one update we
+/// estimate the condition number from the diagonal of the decomposition, and factorize the Gram
+/// matrix again once the estimate exceeds a tolerance, so that ill-conditioned problems are not
+/// solved from a drifting decomposition.
 #[derive(Debug)]
 pub struct RidgeRegression {
-    q: MatrixF64,
-    r: MatrixF64,
-    z: VectorF64,
-    weights: VectorF64,
+    lambda: f64,
+    preprocessing: Preprocessing,
+    batch_threshold: usize,
+    condition_tolerance: f64,
+    num_refactorizations: usize,
+    num_examples: usize,
+    total_weight: f64,
+    feature_means: Array1<f64>,
+    target_means: Array1<f64>,
+    gram: Array2<f64>,
+    z: Array2<f64>,
+    scales: Array1<f64>,
+    cholesky: Cholesky,
+    weights: Array2<f64>,
 }
 
 impl RidgeRegression {
 
-    pub fn new(x: MatrixF64, y: VectorF64, lambda: f64) -> Self {
+    /// Fits the model to the examples in the rows of `x` with their targets in the rows of `y`
+    pub fn new(x: Array2<f64>, y: Array2<f64>, lambda: f64) -> Self {
+        RidgeRegression::with_preprocessing(x, y, lambda, Preprocessing::None)
+    }
+
+    /// Batches with at least as many examples as features are added by refactorizing
+    pub fn with_preprocessing(
+        x: Array2<f64>,
+        y: Array2<f64>,
+        lambda: f64,
+        preprocessing: Preprocessing)
+    -> Self {
+        let num_features = x.cols();
+        RidgeRegression::with_batch_threshold(x, y, lambda, preprocessing, num_features)
+    }
+
+    /// Batches with at least `batch_threshold` examples are added to the model by recomputing
+    /// the Cholesky decomposition once, smaller batches via one rank-one update per example
+    pub fn with_batch_threshold(
+        x: Array2<f64>,
+        y: Array2<f64>,
+        lambda: f64,
+        preprocessing: Preprocessing,
+        batch_threshold: usize)
+    -> Self {
+        let sample_weights = Array1::ones(x.rows());
+        RidgeRegression::with_sample_weights(
+            x, y, lambda, preprocessing, batch_threshold, sample_weights)
+    }
+
+    /// Weighs the examples in the rows of `x` with the given positive weights
+    pub fn with_sample_weights(
+        x: Array2<f64>,
+        y: Array2<f64>,
+        lambda: f64,
+        preprocessing: Preprocessing,
+        batch_threshold: usize,
+        sample_weights: Array1<f64>)
+    -> Self {
+
+        assert_eq!(x.rows(), y.rows(), "Every example needs targets");
+        assert_eq!(x.rows(), sample_weights.len(), "Every example needs a weight");
+        assert!(sample_weights.iter().all(|weight| *weight > 0.0),
+            "Example weights must be positive");
+
+        let num_features = x.cols();
+        let num_targets = y.cols();
+
+        let mut ridge = RidgeRegression {
+            lambda,
+            preprocessing,
+            batch_threshold,
+            condition_tolerance: 1_000_000.0,
+            num_refactorizations: 0,
+            num_examples: 0,
+            total_weight: 0.0,
+            feature_means: Array1::zeros(num_features),
+            target_means: Array1::zeros(num_targets),
+            gram: Array2::zeros((num_features, num_features)),
+            z: Array2::zeros((num_features, num_targets)),
+            scales: Array1::ones(num_features),
+            cholesky: Cholesky::new(&Array2::eye(num_features)).unwrap(),
+            weights: Array2::zeros((num_features, num_targets)),
+        };
+
+        ridge.add_batch(&x, &y, &sample_weights);
+        ridge.refactorize();
+        ridge.solve();
+
+        ridge
+    }
+
+    /// The unpenalized intercepts of the targets, zero when fitting through the origin
+    pub fn intercepts(&self) -> Array1<f64> {
+        self.coefficients().0
+    }
+
+    /// The intercepts and the weights (one column per target) in the original feature space,
+    /// i.e., without centering and scaling of the features
+    pub fn coefficients(&self) -> (Array1<f64>, Array2<f64>) {
+        self.to_original_space(&self.weights)
+    }
+
+    /// Changes the regularization strength. We only have to factorize the maintained Gram
+    /// matrix again, which does not depend on the number of examples.
+    pub fn set_lambda(&mut self, lambda: f64) {
+        self.lambda = lambda;
+        self.refactorize();
+        self.solve();
+    }
+
+    /// Fits the model for each of the given regularization strengths from the maintained Gram
+    /// matrix and returns the coefficients like `coefficients`, the model itself is unchanged
+    pub fn regularization_path(&self, lambdas: &[f64]) -> Vec<(Array1<f64>, Array2<f64>)> {
+        lambdas.iter()
+            .map(|lambda| {
+                let weights = self.factorize(*lambda).solve_columns(&self.scaled_z());
+                self.to_original_space(&weights)
+            })
+            .collect()
+    }
+
+    /// Closed-form leave-one-out residuals `(y - ŷ) / (1 - h)` for the examples the model was
+    /// trained on, where h is the leverage of an example. The intercept counts towards the
+    /// leverage with the share of the example in the total weight. This equals forgetting each
+    /// example and predicting its targets, except for standardization, where we keep the scales
+    /// computed from all examples.
+    pub fn loo_residuals(&self, examples: &[Example]) -> Vec<Array1<f64>> {
+        examples.iter()
+            .map(|example| {
+                let preprocessed = &self.scales * &(&example.features - &self.feature_means);
+
+                let mut leverage = example.weight
+                    * preprocessed.dot(&self.cholesky.solve(&preprocessed));
+                if self.is_centered() {
+                    leverage += example.weight / self.total_weight;
+                }
+
+                (&example.targets - &self.predict(&example.features)) / (1.0 - leverage)
+            })
+            .collect()
+    }
 
-        let num_features = x.size2();
+    fn to_original_space(&self, weights: &Array2<f64>) -> (Array1<f64>, Array2<f64>) {
+        let original_weights = weights * &self.scales.view().insert_axis(Axis(1));
+        let intercepts = &self.target_means - &self.feature_means.dot(&original_weights);
+
+        (intercepts, original_weights)
+    }
+
+    /// Relative residual `|A W - B| / |B|` of the weights in the normal equations `A W = B`
+    /// with `A = X'X + λI` and `B = X'Y` (after preprocessing), computed from the maintained
+    /// Gram matrix. A freshly factorized model has a residual close to machine precision. This
+    /// reveals a decomposition which diverged from the Gram matrix, but not rounding errors in
+    /// the Gram matrix itself, which is why we monitor `condition_estimate` instead.
+    pub fn drift(&self) -> f64 {
+        let scaled_z = self.scaled_z();
+        let column_scales = self.scales.view().insert_axis(Axis(1));
+
+        let residual = self.gram.dot(&(&self.weights * &column_scales)) * column_scales
+            + self.lambda * &self.weights - &scaled_z;
+
+        let squared_residual_norm: f64 = residual.iter().map(|value| value * value).sum();
+        let squared_z_norm: f64 = scaled_z.iter().map(|value| value * value).sum();
+
+        if squared_z_norm > 0.0 {
+            (squared_residual_norm / squared_z_norm).sqrt()
+        } else {
+            squared_residual_norm.sqrt()
+        }
+    }
+
+    /// Estimate of the condition number of X'X + λI (after preprocessing) from the diagonal of
+    /// its Cholesky decomposition, which is a lower bound on the actual condition number
+    pub fn condition_estimate(&self) -> f64 {
+        self.cholesky.condition_estimate()
+    }
+
+    /// Sets the condition estimate above which we factorize the Gram matrix again after a
+    /// rank-one update, a tolerance below one refactorizes after every update
+    pub fn set_condition_tolerance(&mut self, condition_tolerance: f64) {
+        self.condition_tolerance = condition_tolerance;
+    }
+
+    /// Number of refactorizations caused by a condition estimate above the tolerance or by an
+    /// update which lost positive definiteness
+    pub fn num_refactorizations(&self) -> usize {
+        self.num_refactorizations
+    }
+
+    /// Solves for the weights after rank-one updates of the Gram matrix. With standardization,
+    /// the scales change with every example, so we cannot update the factorization and always
+    /// refactorize. Otherwise, we only refactorize if the problem is too ill-conditioned for the
+    /// updated decomposition.
+    fn solve_after_updates(&mut self) {
+        if self.preprocessing == Preprocessing::Standardize {
+            self.refactorize();
+        } else {
+            let condition_estimate = self.condition_estimate();
+            if condition_estimate.is_nan() || condition_estimate > self.condition_tolerance {
+                self.refactorize();
+                self.num_refactorizations += 1;
+            }
+        }
+
+        self.solve();
+    }
+
+    fn is_centered(&self) -> bool {
+        self.preprocessing != Preprocessing::None
+    }
+
+    /// Adds a batch of weighted examples to the Gram matrix. We compute the centered Gram matrix
+    /// of the batch with a single matrix product and merge it with the current one, see Chan et
+    /// al., "Updating formulae and a pairwise algorithm for computing sample variances".
+    fn add_batch(&mut self, x: &Array2<f64>, y: &Array2<f64>, sample_weights: &Array1<f64>) {
+        let (num_rows, num_features) = (x.rows(), x.cols());
+        if num_rows == 0 {
+            return;
+        }
+
+        let batch_weight = sample_weights.sum();
+
+        let (batch_means, batch_target_means) = if self.is_centered() {
+            (sample_weights.dot(x) / batch_weight, sample_weights.dot(y) / batch_weight)
+        } else {
+            (Array1::zeros(num_features), Array1::zeros(y.cols()))
+        };
+
+        let centered_x = x - &batch_means;
+        let centered_y = y - &batch_target_means;
+        let weighted_x = &centered_x * &sample_weights.view().insert_axis(Axis(1));
+
+        let batch_gram = weighted_x.t().dot(&centered_x);
+        let batch_z = weighted_x.t().dot(&centered_y);
+
+        // The difference of the means contributes to the merged centered statistics
+        let weight_after = self.total_weight + batch_weight;
+        let coefficient = self.total_weight * batch_weight / weight_after;
+
+        let delta = &batch_means - &self.feature_means;
+        let target_delta = &batch_target_means - &self.target_means;
+
+        self.gram += &batch_gram;
+        self.gram += &(coefficient * outer(&delta, &delta));
+        self.z += &batch_z;
+        self.z += &(coefficient * outer(&delta, &target_delta));
+
+        self.feature_means.scaled_add(batch_weight / weight_after, &delta);
+        self.target_means.scaled_add(batch_weight / weight_after, &target_delta);
+        self.total_weight = weight_after;
+        self.num_examples += num_rows;
+    }
 
-        let mut xtx = MatrixF64::new(num_features, num_features).expect("Unable to allocate X'X");
-        let mut z = VectorF64::new(num_features).expect("Unable to allocate X'y");
+    /// Adds or removes a single weighted example from the Gram matrix via a rank-one update.
+    /// Without standardization, we apply the same update to the Cholesky decomposition of
+    /// X'X + λI, and factorize again if the update loses positive definiteness. Forgetting from
+    /// an empty model does nothing.
+    fn update(&mut self, example: &Example, forget: bool) {
+        if forget && self.num_examples == 0 {
+            return;
+        }
 
-        dgemm(Trans, NoTrans, 1.0, &x, &x, 0.0, &mut xtx);
-        dgemv(Trans, 1.0, &x, &y, 1.0, &mut z);
+        let weight = example.weight;
+        let weight_before = self.total_weight;
+        let weight_after = if forget { weight_before - weight } else { weight_before + weight };
 
-        for n in 0..num_features {
-            let v = xtx.get(n, n);
-            xtx.set(n, n, v + lambda);
+        if forget {
+            self.num_examples -= 1;
+        } else {
+            self.num_examples += 1;
         }
 
-        let mut tau = VectorF64::new(num_features).expect("Unable to allocate tau");
+        let (coefficient, delta, target_delta) = if !self.is_centered() {
+            let coefficient = if forget { -weight } else { weight };
+            (coefficient, example.features.clone(), example.targets.clone())
+        } else if !forget {
+            // Welford's update, based on the deviation from the mean before the update
+            let delta = &example.features - &self.feature_means;
+            self.feature_means.scaled_add(weight / weight_after, &delta);
+
+            let target_delta = &example.targets - &self.target_means;
+            self.target_means.scaled_add(weight / weight_after, &target_delta);
+
+            (weight_before * weight / weight_after, delta, target_delta)
+        } else {
+            // Reverse of Welford's update, based on the deviation from the mean after the update
+            if self.num_examples > 0 {
+                self.feature_means = (weight_before * &self.feature_means
+                    - weight * &example.features) / weight_after;
+                self.target_means = (weight_before * &self.target_means
+                    - weight * &example.targets) / weight_after;
+            } else {
+                self.feature_means.fill(0.0);
+                self.target_means.fill(0.0);
+            }
+
+            let delta = &example.features - &self.feature_means;
+            let target_delta = &example.targets - &self.target_means;
+            (-weight_after * weight / weight_before, delta, target_delta)
+        };
+
+        // Avoid a total weight which is slightly off zero due to rounding errors
+        self.total_weight = if self.num_examples > 0 { weight_after } else { 0.0 };
+
+        self.gram += &(coefficient * outer(&delta, &delta));
+        self.z += &(coefficient * outer(&delta, &target_delta));
+
+        if self.preprocessing != Preprocessing::Standardize {
+            let scaled_delta = coefficient.abs().sqrt() * delta;
+            let updated = if coefficient >= 0.0 {
+                self.cholesky.update(&scaled_delta)
+            } else {
+                self.cholesky.downdate(&scaled_delta)
+            };
+
+            if !updated {
+                self.refactorize();
+                self.num_refactorizations += 1;
+            }
+        }
+    }
 
-        QR_decomp(&mut xtx, &mut tau);
+    /// Recomputes the feature scales and the Cholesky decomposition of the (scaled) X'X + λI
+    fn refactorize(&mut self) {
+        if self.preprocessing == Preprocessing::Standardize {
+            let total_weight = self.total_weight;
+            self.scales = self.gram.diag().mapv(|squared_deviations| {
+                let variance = squared_deviations / total_weight;
+                // Constant features carry no information, we ignore them
+                if variance > 0.0 { 1.0 / variance.sqrt() } else { 0.0 }
+            });
+        }
 
-        let qr = xtx;
+        self.cholesky = self.factorize(self.lambda);
+    }
 
-        let mut q = MatrixF64::new(num_features, num_features).expect("Unable to allocate Q");
-        let mut r = MatrixF64::new(num_features, num_features).expect("Unable to allocate R");
+    /// Cholesky decomposition of the scaled X'X + λI for the current scales
+    fn factorize(&self, lambda: f64) -> Cholesky {
+        let mut a = outer(&self.scales, &self.scales) * &self.gram;
+        a.diag_mut().map_inplace(|value| *value += lambda);
 
-        QR_unpack(&qr, &tau, &mut q, &mut r);
+        Cholesky::new(&a).expect("X'X + λI is not positive definite, λ must be positive")
+    }
 
-        let mut weights = VectorF64::new(num_features).expect("Unable to allocate tau");
-        QR_QRsolve(&mut q, &mut r, &z, &mut weights);
+    fn scaled_z(&self) -> Array2<f64> {
+        &self.z * &self.scales.view().insert_axis(Axis(1))
+    }
 
-        RidgeRegression { q, r, z, weights }
+    fn solve(&mut self) {
+        self.weights = self.cholesky.solve_columns(&self.scaled_z());
     }
 }
 
-impl IncrementalDecrementalModel<Example, VectorF64, f64> for RidgeRegression {
+/// Outer product ab'
+fn outer(a: &Array1<f64>, b: &Array1<f64>) -> Array2<f64> {
+    Array2::from_shape_fn((a.len(), b.len()), |(row, column)| a[row] * b[column])
+}
+
+impl IncrementalDecrementalModel<Example, Array1<f64>, Array1<f64>> for RidgeRegression {
 
     fn partial_fit(&mut self, data: &[Example]) {
-        //TODO could me more performant to batch updates and recompute QR once
-        for example in data.iter() {
-            let mut w = VectorF64::new(example.features.len()).expect("Unable to allocate w");
+        if data.len() >= self.batch_threshold {
+            let (num_features, num_targets) = self.z.dim();
 
-            dgemv(Trans, 1.0, &self.q, &example.features, 1.0, &mut w);
-            QR_update(&mut self.q, &mut self.r, w, &example.features);
-            daxpy(example.target, &example.features, &mut self.z);
-        }
+            let x = Array2::from_shape_fn((data.len(), num_features), |(row, column)| {
+                data[row].features[column]
+            });
+            let y = Array2::from_shape_fn((data.len(), num_targets), |(row, column)| {
+                data[row].targets[column]
+            });
+            let sample_weights: Array1<f64> = data.iter().map(|example| example.weight).collect();
 
-        QR_QRsolve(&mut self.q, &mut self.r, &self.z, &mut self.weights);
+            self.add_batch(&x, &y, &sample_weights);
+            self.refactorize();
+            self.solve();
+        } else {
+            for example in data.iter() {
+                self.update(example, false);
+            }
+            self.solve_after_updates();
+        }
     }
 
+    /// Forgets an example, which must have the same weight as when it was added
     fn forget(&mut self, example: &Example) {
-        let mut w = VectorF64::new(example.features.len()).expect("Unable to allocate w");
+        self.update(example, true);
+        self.solve_after_updates();
+    }
 
-        dgemv(Trans, -1.0, &self.q, &example.features, 1.0, &mut w);
-        QR_update(&mut self.q, &mut self.r, w, &example.features);
-        daxpy(-1.0 * example.target, &example.features, &mut self.z);
-        QR_QRsolve(&mut self.q, &mut self.r, &self.z, &mut self.weights);
+    /// Predicts all targets
+    fn predict(&self, features: &Array1<f64>) -> Array1<f64> {
+        let preprocessed = &self.scales * &(features - &self.feature_means);
+        &self.target_means + &preprocessed.dot(&self.weights)
     }
+}
+
+/// Weighted ridge regression through the origin, which maintains the inverse (X'WX + λI)⁻¹
+/// directly and adds or forgets examples with the Sherman–Morrison formula. An update only
+/// costs a matrix-vector product, but in contrast to `RidgeRegression`, rounding errors in the
+/// inverse are never corrected, which hurts on ill-conditioned problems. With λ = 0, this is
+/// ordinary least squares, as long as X'WX stays invertible.
+#[derive(Debug)]
+pub struct InverseRidgeRegression {
+    inverse: Array2<f64>,
+    z: Array2<f64>,
+    weights: Array2<f64>,
+}
 
-    fn predict(&self, features: &VectorF64) -> f64 {
-        let mut y_hat = 0_f64;
-        ddot(&features, &self.weights, &mut y_hat);
-        y_hat
+impl InverseRidgeRegression {
+
+    /// Fits the model to the examples in the rows of `x` with their targets in the rows of `y`
+    pub fn new(x: Array2<f64>, y: Array2<f64>, lambda: f64) -> Self {
+        let sample_weights = Array1::ones(x.rows());
+        InverseRidgeRegression::with_sample_weights(x, y, lambda, sample_weights)
+    }
+
+    /// Weighs the examples in the rows of `x` with the given positive weights
+    pub fn with_sample_weights(
+        x: Array2<f64>,
+        y: Array2<f64>,
+        lambda: f64,
+        sample_weights: Array1<f64>)
+    -> Self {
+
+        assert_eq!(x.rows(), y.rows(), "Every example needs targets");
+        assert_eq!(x.rows(), sample_weights.len(), "Every example needs a weight");
+
+        let weighted_x = &x * &sample_weights.view().insert_axis(Axis(1));
+
+        let mut a = weighted_x.t().dot(&x);
+        a.diag_mut().map_inplace(|value| *value += lambda);
+
+        let inverse = Cholesky::new(&a)
+            .expect("X'X + λI is not invertible, λ must be positive")
+            .solve_columns(&Array2::eye(x.cols()));
+
+        let z = weighted_x.t().dot(&y);
+        let weights = inverse.dot(&z);
+
+        InverseRidgeRegression { inverse, z, weights }
+    }
+
+    /// The weights, one column per target
+    pub fn weights(&self) -> &Array2<f64> {
+        &self.weights
+    }
+
+    /// Sherman–Morrison update (A + wxx')⁻¹ = A⁻¹ - w A⁻¹xx'A⁻¹ / (1 + w x'A⁻¹x), a negative
+    /// weight removes the example again
+    fn update(&mut self, example: &Example, weight: f64) {
+        let inverse_x = self.inverse.dot(&example.features);
+        let denominator = 1.0 + weight * example.features.dot(&inverse_x);
+
+        self.inverse -= &(weight / denominator * outer(&inverse_x, &inverse_x));
+        self.z += &(weight * outer(&example.features, &example.targets));
+    }
+}
+
+impl IncrementalDecrementalModel<Example, Array1<f64>, Array1<f64>> for InverseRidgeRegression {
+
+    fn partial_fit(&mut self, data: &[Example]) {
+        for example in data.iter() {
+            self.update(example, example.weight);
+        }
+        self.weights = self.inverse.dot(&self.z);
+    }
+
+    /// Forgets an example, which must have the same weight as when it was added
+    fn forget(&mut self, example: &Example) {
+        self.update(example, -example.weight);
+        self.weights = self.inverse.dot(&self.z);
+    }
+
+    /// Predicts all targets
+    fn predict(&self, features: &Array1<f64>) -> Array1<f64> {
+        features.dot(&self.weights)
     }
 }
 
 #[cfg(test)]
 mod tests {
 
-    extern crate rgsl;
+    use ndarray::{Array1, Array2};
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::StdRng;
 
-    use rgsl::{VectorF64, MatrixView, VectorView};
-
-    use crate::ridge::RidgeRegression;
+    use crate::ridge::{RidgeRegression, InverseRidgeRegression, Preprocessing};
     use crate::ridge::Example;
     use crate::IncrementalDecrementalModel;
+    use crate::cross_validation::leave_one_out;
 
     #[test]
     fn forget_one() {
 
-        let mut x = MatrixView::from_array(&mut [
-                0.130010, -0.223675,
-                -0.504190, -0.223675,
-                0.502476, -0.223675,
-                -0.735723, -1.537767,
-                1.257476,  1.090417],
-            5, 2);
-
-        let mut y = VectorView::from_array(
-            &mut [0.475747, -0.084074, 0.228626, -0.867025,  1.595389]
-        );
+        let x = array![
+            [0.130010, -0.223675],
+            [-0.504190, -0.223675],
+            [0.502476, -0.223675],
+            [-0.735723, -1.537767],
+            [1.257476,  1.090417]];
 
+        let y = array![[0.475747], [-0.084074], [0.228626], [-0.867025],  [1.595389]];
 
-        let mut ridge = RidgeRegression::new(x.matrix(), y.vector(), 0.001);
+        let mut ridge = RidgeRegression::new(x, y, 0.001);
 
-        let example_to_forget = Example::new(
-            VectorView::from_array(&mut [1.257476,  1.090417]).vector(),
-            1.595389
-        );
+        let example_to_forget = Example::new(array![1.257476,  1.090417], 1.595389);
 
         ridge.forget(&example_to_forget);
 
-        println!("RIDGE FORGET\n{:?}", ridge);
+        let x2 = array![
+            [0.130010, -0.223675],
+            [-0.504190, -0.223675],
+            [0.502476, -0.223675],
+            [-0.735723, -1.537767]];
+
+        let y2 = array![[0.475747], [-0.084074], [0.228626], [-0.867025]];
+
+        let ridge2 = RidgeRegression::new(x2, y2, 0.001);
+
+        assert!(max_coefficient_difference(&ridge, &ridge2) < 0.000001);
+    }
+
+    const ROWS: [[f64; 2]; 5] = [
+        [0.130010, -0.223675],
+        [-0.504190, -0.223675],
+        [0.502476, -0.223675],
+        [-0.735723, -1.537767],
+        [1.257476, 1.090417],
+    ];
+    const TARGETS: [f64; 5] = [0.475747, -0.084074, 0.228626, -0.867025, 1.595389];
+    const SECOND_TARGETS: [f64; 5] = [-1.2, 0.3, 0.8, -0.4, 2.1];
+
+    const ALL_PREPROCESSING: [Preprocessing; 3] =
+        [Preprocessing::None, Preprocessing::Center, Preprocessing::Standardize];
+
+    fn examples() -> Vec<Example> {
+        ROWS.iter().zip(TARGETS.iter())
+            .map(|(row, target)| Example::new(Array1::from_vec(row.to_vec()), *target))
+            .collect()
+    }
+
+    fn multi_target_examples() -> Vec<Example> {
+        ROWS.iter().zip(TARGETS.iter().zip(SECOND_TARGETS.iter()))
+            .map(|(row, (target, second_target))| {
+                let targets = Array1::from_vec(vec![*target, *second_target]);
+                Example::with_targets(Array1::from_vec(row.to_vec()), targets)
+            })
+            .collect()
+    }
+
+    fn to_matrix(examples: &[Example]) -> (Array2<f64>, Array2<f64>) {
+        let num_targets = examples[0].targets.len();
+        let x = Array2::from_shape_fn((examples.len(), 2), |(row, column)| {
+            examples[row].features[column]
+        });
+        let y = Array2::from_shape_fn((examples.len(), num_targets), |(row, column)| {
+            examples[row].targets[column]
+        });
+        (x, y)
+    }
+
+    fn assert_close(values: &Array1<f64>, other_values: &Array1<f64>) {
+        assert_eq!(values.len(), other_values.len());
+        for (value, other_value) in values.iter().zip(other_values.iter()) {
+            assert!((value - other_value).abs() < 0.000001);
+        }
+    }
+
+    fn assert_same_predictions(ridge: &RidgeRegression, other_ridge: &RidgeRegression) {
+        for example in examples().iter() {
+            let predictions = ridge.predict(&example.features);
+            let other_predictions = other_ridge.predict(&example.features);
+            assert_close(&predictions, &other_predictions);
+        }
+    }
+
+    /// Largest absolute difference between the intercepts and weights of both models
+    fn max_coefficient_difference(ridge: &RidgeRegression, other_ridge: &RidgeRegression) -> f64 {
+        let (intercepts, weights) = ridge.coefficients();
+        let (other_intercepts, other_weights) = other_ridge.coefficients();
+
+        intercepts.iter().zip(other_intercepts.iter())
+            .chain(weights.iter().zip(other_weights.iter()))
+            .map(|(value, other_value)| (value - other_value).abs())
+            .fold(0.0, f64::max)
+    }
+
+    /// Weighted examples with two targets from a noisy linear model with an offset
+    fn random_examples(rng: &mut StdRng, num_examples: usize, num_features: usize) -> Vec<Example> {
+        let true_weights = Array2::from_shape_fn((num_features, 2), |_| rng.gen_range(-2.0, 2.0));
+
+        (0..num_examples)
+            .map(|_| {
+                let features = Array1::from_shape_fn(num_features, |_| rng.gen_range(-1.0, 1.0));
+                let noise = Array1::from_shape_fn(2, |_| rng.gen_range(-0.1, 0.1));
+                let targets = features.dot(&true_weights) + noise + 3.0;
+                Example::with_weight(features, targets, rng.gen_range(0.5, 2.0))
+            })
+            .collect()
+    }
+
+    fn retrain(
+        examples: &[Example],
+        num_features: usize,
+        lambda: f64,
+        preprocessing: Preprocessing)
+    -> RidgeRegression {
+        let x = Array2::from_shape_fn((examples.len(), num_features), |(row, column)| {
+            examples[row].features[column]
+        });
+        let y = Array2::from_shape_fn((examples.len(), 2), |(row, column)| {
+            examples[row].targets[column]
+        });
+        let sample_weights = examples.iter().map(|example| example.weight).collect();
+
+        RidgeRegression::with_sample_weights(
+            x, y, lambda, preprocessing, num_features, sample_weights)
+    }
+
+    #[test]
+    fn batched_partial_fit() {
+        let examples = examples();
+        let (x, y) = to_matrix(&examples[..2]);
+
+        for preprocessing in ALL_PREPROCESSING.iter() {
+            // Rank-one updates per example versus a single refactorization of the whole batch
+            let mut ridge_per_example = RidgeRegression::with_batch_threshold(
+                x.clone(), y.clone(), 0.001, *preprocessing, usize::MAX);
+            ridge_per_example.partial_fit(&examples[2..]);
+
+            let mut ridge_batched = RidgeRegression::with_batch_threshold(
+                x.clone(), y.clone(), 0.001, *preprocessing, 1);
+            ridge_batched.partial_fit(&examples[2..]);
+
+            assert_same_predictions(&ridge_per_example, &ridge_batched);
+        }
+    }
+
+    #[test]
+    fn forgetting_matches_retraining_with_preprocessing() {
+        let examples = examples();
+
+        for preprocessing in ALL_PREPROCESSING.iter() {
+            let (x, y) = to_matrix(&examples);
+            let mut ridge = RidgeRegression::with_preprocessing(x, y, 0.001, *preprocessing);
+            ridge.forget(&examples[4]);
+            ridge.forget(&examples[0]);
+
+            let (x, y) = to_matrix(&examples[1..4]);
+            let retrained = RidgeRegression::with_preprocessing(x, y, 0.001, *preprocessing);
+
+            assert_same_predictions(&ridge, &retrained);
+            assert_close(&ridge.intercepts(), &retrained.intercepts());
+        }
+    }
+
+    #[test]
+    fn intercept_is_not_penalized() {
+        let shifted_examples: Vec<Example> = examples().into_iter()
+            .map(|example| Example::with_targets(example.features, example.targets + 10.0))
+            .collect();
+        let (x, y) = to_matrix(&shifted_examples);
+
+        // A strong penalty shrinks all weights towards zero, but not the intercept
+        let ridge = RidgeRegression::with_preprocessing(x, y, 1000.0, Preprocessing::Center);
+
+        let mean_target = shifted_examples.iter().map(|example| example.targets[0]).sum::<f64>()
+            / 5.0;
+        assert!((ridge.intercepts()[0] - mean_target).abs() < 0.01);
+    }
+
+    #[test]
+    fn changing_lambda_matches_retraining() {
+        for preprocessing in ALL_PREPROCESSING.iter() {
+            let (x, y) = to_matrix(&examples());
+            let mut ridge = RidgeRegression::with_preprocessing(x, y, 0.001, *preprocessing);
+            ridge.set_lambda(2.0);
+
+            let (x, y) = to_matrix(&examples());
+            let retrained = RidgeRegression::with_preprocessing(x, y, 2.0, *preprocessing);
+
+            assert_same_predictions(&ridge, &retrained);
+        }
+    }
+
+    #[test]
+    fn regularization_path() {
+        let lambdas = [0.001, 0.1, 10.0];
+
+        for preprocessing in ALL_PREPROCESSING.iter() {
+            let (x, y) = to_matrix(&examples());
+            let ridge = RidgeRegression::with_preprocessing(x, y, 1.0, *preprocessing);
+
+            let path = ridge.regularization_path(&lambdas);
+            assert_eq!(path.len(), lambdas.len());
+
+            for (lambda, (intercepts, weights)) in lambdas.iter().zip(path.iter()) {
+                let (x, y) = to_matrix(&examples());
+                let retrained = RidgeRegression::with_preprocessing(x, y, *lambda, *preprocessing);
+                let (retrained_intercepts, retrained_weights) = retrained.coefficients();
+
+                assert_close(intercepts, &retrained_intercepts);
+                let (column, retrained_column) = (weights.column(0), retrained_weights.column(0));
+                assert_close(&column.to_owned(), &retrained_column.to_owned());
+            }
+        }
+    }
+
+    #[test]
+    fn closed_form_loo_residuals() {
+        let mut examples = examples();
+        examples[1].weight = 2.5;
+
+        for preprocessing in &[Preprocessing::None, Preprocessing::Center] {
+            let mut ridge = RidgeRegression::with_batch_threshold(
+                Array2::zeros((0, 2)), Array2::zeros((0, 1)), 0.1, *preprocessing, 1);
+            ridge.partial_fit(&examples);
+
+            let residuals = ridge.loo_residuals(&examples);
+
+            let loo_residuals = leave_one_out(&mut ridge, &examples, |ridge, example| {
+                example.targets[0] - ridge.predict(&example.features)[0]
+            });
+
+            for (residual, loo_residual) in residuals.iter().zip(loo_residuals.iter()) {
+                assert!((residual[0] - loo_residual).abs() < 0.000001);
+            }
+        }
+    }
+
+    #[test]
+    fn refactorizes_when_ill_conditioned() {
+        let examples = examples();
+
+        let (x, y) = to_matrix(&examples);
+        let mut ridge = RidgeRegression::new(x, y, 0.001);
+
+        // Forgetting and re-adding examples many times keeps the weights accurate
+        for _ in 0..100 {
+            for example in examples[1..].iter() {
+                ridge.forget(example);
+            }
+            ridge.partial_fit(&examples[1..]);
+        }
+        assert!(ridge.drift() <= 0.000001);
+
+        let (x, y) = to_matrix(&examples);
+        let retrained = RidgeRegression::new(x, y, 0.001);
+        assert_same_predictions(&ridge, &retrained);
 
-        let mut x2 = MatrixView::from_array(&mut [
-            0.130010, -0.223675,
-            -0.504190, -0.223675,
-            0.502476, -0.223675,
-            -0.735723, -1.537767],
-            4, 2);
+        let num_refactorizations = ridge.num_refactorizations();
+        ridge.set_condition_tolerance(ridge.condition_estimate() * 2.0);
+        ridge.forget(&examples[0]);
+        assert_eq!(ridge.num_refactorizations(), num_refactorizations);
 
-        let mut y2 = VectorView::from_array(
-            &mut [0.475747, -0.084074, 0.228626, -0.867025]
-        );
+        ridge.set_condition_tolerance(0.0);
+        ridge.partial_fit(&examples[..1]);
+        assert_eq!(ridge.num_refactorizations(), num_refactorizations + 1);
+    }
+
+    #[test]
+    fn multiple_targets_match_separate_models() {
+        let examples = multi_target_examples();
+
+        for preprocessing in ALL_PREPROCESSING.iter() {
+            let (x, y) = to_matrix(&examples);
+            let mut ridge = RidgeRegression::with_preprocessing(
+                x.clone(), y.clone(), 0.01, *preprocessing);
+            ridge.forget(&examples[2]);
+
+            for target in 0..2 {
+                let single_target_examples: Vec<Example> = examples.iter()
+                    .map(|example| Example::new(example.features.clone(), example.targets[target]))
+                    .collect();
+
+                let (x, y) = to_matrix(&single_target_examples);
+                let mut single_target_ridge =
+                    RidgeRegression::with_preprocessing(x, y, 0.01, *preprocessing);
+                single_target_ridge.forget(&single_target_examples[2]);
+
+                for example in examples.iter() {
+                    let prediction = ridge.predict(&example.features)[target];
+                    let single_prediction = single_target_ridge.predict(&example.features)[0];
+                    assert!((prediction - single_prediction).abs() < 0.000001);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn weights_match_duplicated_examples() {
+        let examples = examples();
+
+        for preprocessing in ALL_PREPROCESSING.iter() {
+            // An example with weight two counts like the same example added twice
+            let (x, y) = to_matrix(&examples);
+            let sample_weights = array![1.0, 2.0, 1.0, 1.0, 1.0];
+            let weighted = RidgeRegression::with_sample_weights(
+                x, y, 0.01, *preprocessing, 2, sample_weights);
+
+            let mut duplicated_examples = examples.clone();
+            duplicated_examples.push(examples[1].clone());
+            let (x, y) = to_matrix(&duplicated_examples);
+            let duplicated = RidgeRegression::with_preprocessing(x, y, 0.01, *preprocessing);
+
+            assert_same_predictions(&weighted, &duplicated);
+
+            // Adding a weighted example per rank-one update and in a batch
+            let weighted_example = Example::with_weight(
+                examples[3].features.clone(), examples[3].targets.clone(), 0.5);
+
+            for batch_threshold in &[1, usize::MAX] {
+                let (x, y) = to_matrix(&examples[..3]);
+                let mut ridge = RidgeRegression::with_batch_threshold(
+                    x, y, 0.01, *preprocessing, *batch_threshold);
+                ridge.partial_fit(&[weighted_example.clone(), examples[4].clone()]);
 
-        let mut ridge2 = RidgeRegression::new(x2.matrix(), y2.vector(), 0.001);
+                let (x, y) = to_matrix(&examples);
+                let sample_weights = array![1.0, 1.0, 1.0, 0.5, 1.0];
+                let retrained = RidgeRegression::with_sample_weights(
+                    x, y, 0.01, *preprocessing, 2, sample_weights);
 
-        println!("RIDGE RETRAIN\n{:?}", ridge2);
+                assert_same_predictions(&ridge, &retrained);
+            }
+        }
+    }
+
+    #[test]
+    fn forgetting_weighted_examples_matches_retraining() {
+        let examples: Vec<Example> = multi_target_examples().into_iter()
+            .enumerate()
+            .map(|(index, example)| {
+                Example::with_weight(example.features, example.targets, 0.5 + index as f64)
+            })
+            .collect();
 
-        //TODO check that ridge and ridge2 are close enough
+        for preprocessing in ALL_PREPROCESSING.iter() {
+            let mut ridge = RidgeRegression::with_batch_threshold(
+                Array2::zeros((0, 2)), Array2::zeros((0, 2)), 0.01, *preprocessing, usize::MAX);
+            ridge.partial_fit(&examples);
+            ridge.forget(&examples[3]);
+            ridge.forget(&examples[0]);
+
+            let remaining = [examples[1].clone(), examples[2].clone(), examples[4].clone()];
+            let (x, y) = to_matrix(&remaining);
+            let sample_weights = array![1.5, 2.5, 4.5];
+            let retrained = RidgeRegression::with_sample_weights(
+                x, y, 0.01, *preprocessing, 2, sample_weights);
+
+            assert_same_predictions(&ridge, &retrained);
+            assert_close(&ridge.intercepts(), &retrained.intercepts());
+        }
     }
 
+    #[test]
+    fn inverse_model_matches_factorized_model() {
+        let examples: Vec<Example> = multi_target_examples().into_iter()
+            .enumerate()
+            .map(|(index, example)| {
+                Example::with_weight(example.features, example.targets, 0.5 + index as f64)
+            })
+            .collect();
+
+        let (x, y) = to_matrix(&examples[..3]);
+        let sample_weights = array![0.5, 1.5, 2.5];
+        let mut inverse_ridge = InverseRidgeRegression::with_sample_weights(
+            x.clone(), y.clone(), 0.01, sample_weights.clone());
+        let mut ridge = RidgeRegression::with_sample_weights(
+            x, y, 0.01, Preprocessing::None, usize::MAX, sample_weights);
+
+        inverse_ridge.partial_fit(&examples[3..]);
+        ridge.partial_fit(&examples[3..]);
+        inverse_ridge.forget(&examples[1]);
+        ridge.forget(&examples[1]);
+
+        for example in examples.iter() {
+            let predictions = inverse_ridge.predict(&example.features);
+            assert_close(&predictions, &ridge.predict(&example.features));
+        }
+
+        let (_intercepts, weights) = ridge.coefficients();
+        for (weight, other_weight) in inverse_ridge.weights().iter().zip(weights.iter()) {
+            assert!((weight - other_weight).abs() < 0.000001);
+        }
+    }
 
+    #[test]
+    fn forgetting_matches_retraining_on_random_designs() {
+        let mut rng = StdRng::seed_from_u64(7);
+
+        for _ in 0..10 {
+            let examples = random_examples(&mut rng, 30, 4);
+
+            for preprocessing in ALL_PREPROCESSING.iter() {
+                let mut ridge = retrain(&examples, 4, 0.01, *preprocessing);
+                let mut remaining = examples.clone();
+
+                for _ in 0..10 {
+                    let example = remaining.remove(rng.gen_range(0, remaining.len()));
+                    ridge.forget(&example);
+                }
+
+                let retrained = retrain(&remaining, 4, 0.01, *preprocessing);
+                assert!(max_coefficient_difference(&ridge, &retrained) < 0.000001);
+            }
+        }
+    }
+
+    #[test]
+    fn forgetting_every_example_in_sequence() {
+        let mut rng = StdRng::seed_from_u64(11);
+        let examples = random_examples(&mut rng, 12, 3);
+
+        for preprocessing in ALL_PREPROCESSING.iter() {
+            let mut ridge = retrain(&examples, 3, 0.01, *preprocessing);
+
+            for (index, example) in examples.iter().enumerate() {
+                ridge.forget(example);
+
+                let retrained = retrain(&examples[index + 1..], 3, 0.01, *preprocessing);
+                assert!(max_coefficient_difference(&ridge, &retrained) < 0.000001);
+            }
+
+            // Without any examples, there is nothing left to predict from
+            let (intercepts, weights) = ridge.coefficients();
+            assert!(intercepts.iter().chain(weights.iter()).all(|value| value.abs() < 0.000001));
+
+            // Forgetting from the empty model does nothing
+            ridge.forget(&examples[0]);
+            let retrained = retrain(&[], 3, 0.01, *preprocessing);
+            assert!(max_coefficient_difference(&ridge, &retrained) < 0.000001);
+        }
+    }
+
+    #[test]
+    fn interleaved_partial_fit_and_forget() {
+        let mut rng = StdRng::seed_from_u64(13);
+        let examples = random_examples(&mut rng, 40, 3);
+
+        for preprocessing in ALL_PREPROCESSING.iter() {
+            let mut ridge = retrain(&examples[..5], 3, 0.01, *preprocessing);
+            let mut active: Vec<Example> = examples[..5].to_vec();
+            let mut inactive: Vec<Example> = examples[5..].to_vec();
+
+            for _ in 0..100 {
+                if !inactive.is_empty() && (active.len() < 2 || rng.gen::<bool>()) {
+                    // Add one or a few examples, which includes batches above the threshold
+                    let num_to_add = rng.gen_range(1, 5).min(inactive.len());
+                    let added: Vec<Example> = inactive.drain(..num_to_add).collect();
+                    ridge.partial_fit(&added);
+                    active.extend(added);
+                } else {
+                    let example = active.remove(rng.gen_range(0, active.len()));
+                    ridge.forget(&example);
+                    inactive.push(example);
+                }
+
+                let retrained = retrain(&active, 3, 0.01, *preprocessing);
+                assert!(max_coefficient_difference(&ridge, &retrained) < 0.000001);
+            }
+        }
+    }
+
+    #[test]
+    fn forgetting_in_ill_conditioned_case() {
+        let mut rng = StdRng::seed_from_u64(17);
+
+        // The second feature almost equals the first one, so X'X is close to singular
+        let examples: Vec<Example> = random_examples(&mut rng, 30, 3).into_iter()
+            .map(|mut example| {
+                example.features[1] = example.features[0] + rng.gen_range(-0.0001, 0.0001);
+                example
+            })
+            .collect();
+
+        for preprocessing in ALL_PREPROCESSING.iter() {
+            let mut ridge = retrain(&examples, 3, 0.00000001, *preprocessing);
+
+            for (index, example) in examples[..10].iter().enumerate() {
+                ridge.forget(example);
+                assert!(ridge.drift() <= 0.000001);
+
+                // The individual weights of the collinear features are hardly determined by the
+                // data, but their predictions are
+                let retrained = retrain(&examples[index + 1..], 3, 0.00000001, *preprocessing);
+                for other_example in examples.iter() {
+                    let predictions = ridge.predict(&other_example.features);
+                    let retrained_predictions = retrained.predict(&other_example.features);
+                    for (prediction, retrained_prediction) in
+                        predictions.iter().zip(retrained_predictions.iter()) {
+                        assert!((prediction - retrained_prediction).abs() < 0.0001);
+                    }
+                }
+            }
+
+            // The rank-one downdates are not trusted for such a badly conditioned problem
+            assert!(ridge.condition_estimate() > 1_000_000.0);
+            if *preprocessing != Preprocessing::Standardize {
+                assert_eq!(ridge.num_refactorizations(), 10);
+            }
+        }
+    }
 }
@@ -0,0 +1,89 @@
+/// Similarity measures between two items, computed from the number of users which interacted with
+/// both items (`cooccurrences`), the number of users which interacted with each of the items
+/// (`n_a`, `n_b`) and the overall number of users with at least one interaction (`num_users`).
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Similarity {
+    /// Number of users which interacted with both items divided by the number of users which
+    /// interacted with at least one of them
+    #[default]
+    Jaccard,
+    /// Cosine similarity of the binary interaction vectors of the items
+    Cosine,
+    /// Conditional probability that a user interacted with item b given that they interacted with
+    /// item a, this measure is not symmetric
+    ConditionalProbability,
+    /// Dunning's log-likelihood ratio, normalized to `[0, 1)` via `1 - 1 / (1 + llr)`
+    LogLikelihoodRatio,
+    /// Pointwise mutual information of the interactions with both items
+    PointwiseMutualInformation,
+}
+
+impl Similarity {
+
+    /// Computes the similarity of item b to item a, only defined for pairs which cooccur at least
+    /// once. The result is always finite in that case.
+    pub fn score(&self, cooccurrences: u64, n_a: u64, n_b: u64, num_users: u64) -> f64 {
+        debug_assert!(cooccurrences > 0 && cooccurrences <= n_a && cooccurrences <= n_b);
+
+        match self {
+            Similarity::Jaccard => {
+                // The denominator is computed on integers and is at least `cooccurrences`
+                cooccurrences as f64 / (n_a + n_b - cooccurrences) as f64
+            },
+            Similarity::Cosine => {
+                cooccurrences as f64 / ((n_a as f64) * (n_b as f64)).sqrt()
+            },
+            Similarity::ConditionalProbability => {
+                cooccurrences as f64 / n_a as f64
+            },
+            Similarity::LogLikelihoodRatio => {
+                let k11 = cooccurrences;
+                let k12 = n_b - cooccurrences;
+                let k21 = n_a - cooccurrences;
+                let k22 = num_users.saturating_sub(n_a + n_b - cooccurrences);
+
+                let llr = log_likelihood_ratio(k11 as f64, k12 as f64, k21 as f64, k22 as f64);
+                1.0 - 1.0 / (1.0 + llr)
+            },
+            Similarity::PointwiseMutualInformation => {
+                ((cooccurrences as f64 * num_users as f64) / (n_a as f64 * n_b as f64)).ln()
+            },
+        }
+    }
+
+    /// Whether the score of a pair changes with the overall number of users, in which case all
+    /// pairs have to be rescored whenever users are added or forgotten
+    pub fn depends_on_num_users(&self) -> bool {
+        matches!(self, Similarity::LogLikelihoodRatio | Similarity::PointwiseMutualInformation)
+    }
+
+    /// Whether the similarity of a to b is the same as the similarity of b to a
+    pub fn is_symmetric(&self) -> bool {
+        !matches!(self, Similarity::ConditionalProbability)
+    }
+}
+
+fn x_log_x(x: f64) -> f64 {
+    if x == 0.0 { 0.0 } else { x * x.ln() }
+}
+
+/// Unnormalized Shannon entropy of a set of counts
+fn entropy(counts: &[f64]) -> f64 {
+    let sum: f64 = counts.iter().sum();
+    x_log_x(sum) - counts.iter().map(|count| x_log_x(*count)).sum::<f64>()
+}
+
+/// Log-likelihood ratio of a 2x2 contingency table, see Dunning, "Accurate methods for the
+/// statistics of surprise and coincidence"
+fn log_likelihood_ratio(k11: f64, k12: f64, k21: f64, k22: f64) -> f64 {
+    let row_entropy = entropy(&[k11 + k12, k21 + k22]);
+    let column_entropy = entropy(&[k11 + k21, k12 + k22]);
+    let matrix_entropy = entropy(&[k11, k12, k21, k22]);
+
+    // Guard against negative values caused by rounding errors
+    if row_entropy + column_entropy < matrix_entropy {
+        0.0
+    } else {
+        2.0 * (row_entropy + column_entropy - matrix_entropy)
+    }
+}
@@ -0,0 +1,104 @@
+use std::borrow::Cow;
+use std::hash::Hasher;
+use fnv::FnvHasher;
+
+/// Cap on the number of interactions per user which are used for item-based CF. Very active
+/// users contribute a quadratic number of cooccurrences, so we limit their influence. The cap is
+/// applied identically by `ItembasedCF` and the differential `itembased_cf`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UserCap {
+    pub max_interactions: usize,
+    pub policy: CapPolicy,
+}
+
+/// How to handle users with more than `max_interactions` interactions
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CapPolicy {
+    /// Ignore all interactions of the user
+    Drop,
+    /// Keep the interactions with the `max_interactions` smallest item ids. The differential
+    /// implementation does not see the arrival order of interactions, so we truncate in item order.
+    Truncate,
+    /// Keep a pseudo-random sample of `max_interactions` items, which only depends on the hashes
+    /// of the user and item ids, so that the sample is the same in both implementations
+    Sample,
+}
+
+impl UserCap {
+
+    pub fn new(max_interactions: usize, policy: CapPolicy) -> Self {
+        UserCap { max_interactions, policy }
+    }
+
+    pub fn unlimited() -> Self {
+        UserCap::new(usize::MAX, CapPolicy::Drop)
+    }
+
+    /// Returns the distinct items of the user which remain after applying the cap, sorted by item
+    /// id. Repeated interactions with the same item do not count towards the cap. Sorted
+    /// histories of distinct items within the cap are returned without copying them.
+    pub fn apply<'a>(&self, user: u32, items: &'a [u32]) -> Cow<'a, [u32]> {
+        let is_distinct_and_sorted = items.windows(2).all(|pair| pair[0] < pair[1]);
+        if is_distinct_and_sorted && items.len() <= self.max_interactions {
+            return Cow::Borrowed(items);
+        }
+
+        let mut distinct_items = items.to_vec();
+        distinct_items.sort();
+        distinct_items.dedup();
+
+        if distinct_items.len() <= self.max_interactions {
+            return Cow::Owned(distinct_items);
+        }
+
+        match self.policy {
+            CapPolicy::Drop => Cow::Borrowed(&[]),
+            CapPolicy::Truncate => {
+                distinct_items.truncate(self.max_interactions);
+                Cow::Owned(distinct_items)
+            },
+            CapPolicy::Sample => {
+                distinct_items.sort_by_key(|item| (sample_key(user, *item), *item));
+                distinct_items.truncate(self.max_interactions);
+                distinct_items.sort();
+                Cow::Owned(distinct_items)
+            }
+        }
+    }
+}
+
+fn sample_key(user: u32, item: u32) -> u64 {
+    let mut hasher = FnvHasher::default();
+    hasher.write_u32(user);
+    hasher.write_u32(item);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::borrow::Cow;
+
+    use crate::user_cap::{UserCap, CapPolicy};
+
+    #[test]
+    fn policies() {
+        let items = vec![5, 1, 4, 2, 3];
+
+        assert_eq!(UserCap::new(5, CapPolicy::Drop).apply(0, &items), vec![1, 2, 3, 4, 5]);
+        assert!(UserCap::new(4, CapPolicy::Drop).apply(0, &items).is_empty());
+        assert_eq!(UserCap::new(3, CapPolicy::Truncate).apply(0, &items), vec![1, 2, 3]);
+
+        let sample = UserCap::new(3, CapPolicy::Sample).apply(7, &items);
+        assert_eq!(sample.len(), 3);
+        assert!(sample.iter().all(|item| items.contains(item)));
+        assert_eq!(sample, UserCap::new(3, CapPolicy::Sample).apply(7, &[3, 2, 1, 4, 5]));
+
+        // Duplicates do not count towards the cap
+        assert_eq!(UserCap::new(2, CapPolicy::Drop).apply(0, &[1, 1, 2]), vec![1, 2]);
+
+        // Sorted histories within the cap are not copied
+        let capped_items = UserCap::new(5, CapPolicy::Drop).apply(0, &[1, 2, 3]);
+        assert!(matches!(capped_items, Cow::Borrowed(_)));
+    }
+}
@@ -0,0 +1,318 @@
+use fnv::{FnvHashMap, FnvHashSet};
+
+use crate::IncrementalDecrementalModel;
+use crate::itembased::{top_k, ScoredItem};
+use crate::similarity::Similarity;
+
+/// User-based collaborative filtering. We maintain the number of items which each pair of users
+/// interacted with (their overlap) via an inverted index from items to users, and derive the
+/// user-user similarities from the overlaps. The similarity measures are the same as for
+/// `ItembasedCF` with the roles of users and items swapped, so `num_users` in `Similarity::score`
+/// refers to the number of items with at least one interaction here.
+#[derive(Debug)]
+pub struct UserbasedCF {
+    k: usize,
+    similarity: Similarity,
+    histories: FnvHashMap<u32, FnvHashSet<u32>>,
+    item_users: FnvHashMap<u32, FnvHashSet<u32>>,
+    overlaps: FnvHashMap<u32, FnvHashMap<u32, u32>>,
+    s: FnvHashMap<u32, FnvHashMap<u32, f32>>,
+}
+
+impl UserbasedCF {
+
+    pub fn new(k: usize) -> Self {
+        UserbasedCF::with_similarity(k, Similarity::Jaccard)
+    }
+
+    pub fn with_similarity(k: usize, similarity: Similarity) -> Self {
+        UserbasedCF {
+            k,
+            similarity,
+            histories: FnvHashMap::default(),
+            item_users: FnvHashMap::default(),
+            overlaps: FnvHashMap::default(),
+            s: FnvHashMap::default(),
+        }
+    }
+
+    /// Forgets all interactions of a user, which removes the user from every neighbour list
+    pub fn forget_user(&mut self, user: u32) {
+        if let Some(history) = self.histories.get(&user) {
+            let items: Vec<u32> = history.iter().cloned().collect();
+            self.forget(&(user, items));
+        }
+    }
+
+    /// Recommends the `n` highest scoring items which the user has not interacted with yet. Every
+    /// one of the k most similar users adds their similarity to each item in their history, ties
+    /// are broken in favor of the smaller item id.
+    pub fn recommend(&self, user: u32, n: usize) -> Vec<(u32, f32)> {
+        let seen_items = match self.histories.get(&user) {
+            Some(history) => history,
+            None => return Vec::new(),
+        };
+
+        let mut scores: FnvHashMap<u32, f32> = FnvHashMap::default();
+        for neighbour in self.top_k_neighbours(user) {
+            for item in self.histories[&neighbour.item].iter() {
+                if !seen_items.contains(item) {
+                    *scores.entry(*item).or_insert(0.0) += neighbour.score;
+                }
+            }
+        }
+
+        let mut recommendations: Vec<ScoredItem> = scores.into_iter()
+            .map(|(item, score)| ScoredItem { item, score })
+            .collect();
+
+        // ScoredItem is ordered by descending score
+        recommendations.sort();
+        recommendations.truncate(n);
+
+        recommendations.into_iter()
+            .map(|scored_item| (scored_item.item, scored_item.score))
+            .collect()
+    }
+
+    fn top_k_neighbours(&self, user: u32) -> Vec<ScoredItem> {
+        match self.s.get(&user) {
+            Some(similarities) => top_k(similarities, self.k),
+            None => Vec::new(),
+        }
+    }
+
+    /// Updates the overlaps of the user with all other users of the item, returns whether the
+    /// item had no interactions before
+    fn add_interaction(&mut self, user: u32, item: u32) -> bool {
+        let users = self.item_users.entry(item).or_default();
+        let new_item = users.is_empty();
+
+        for other_user in users.iter() {
+            *self.overlaps.entry(user).or_default().entry(*other_user).or_insert(0) += 1;
+            *self.overlaps.entry(*other_user).or_default().entry(user).or_insert(0) += 1;
+        }
+        users.insert(user);
+
+        new_item
+    }
+
+    /// Reverts `add_interaction`, returns whether the item has no interactions anymore
+    fn remove_interaction(&mut self, user: u32, item: u32) -> bool {
+        let users = self.item_users.get_mut(&item).unwrap();
+        users.remove(&user);
+
+        for other_user in users.iter() {
+            let overlap = self.overlaps.get_mut(&user).unwrap().get_mut(other_user).unwrap();
+            *overlap -= 1;
+
+            let no_overlap = *overlap == 0;
+            *self.overlaps.get_mut(other_user).unwrap().get_mut(&user).unwrap() -= 1;
+
+            // Drop users without any overlap
+            if no_overlap {
+                for (a, b) in &[(user, *other_user), (*other_user, user)] {
+                    remove_entry(&mut self.overlaps, *a, *b);
+                    remove_entry(&mut self.s, *a, *b);
+                }
+            }
+        }
+
+        let removed_item = users.is_empty();
+        if removed_item {
+            self.item_users.remove(&item);
+        }
+
+        removed_item
+    }
+
+    /// Recomputes the similarities of all user pairs which involve one of the given users.
+    /// Measures which depend on the number of items require us to rescore all pairs.
+    fn rescore(&mut self, users: &FnvHashSet<u32>, num_items_changed: bool) {
+        let users: Vec<u32> = if num_items_changed && self.similarity.depends_on_num_users() {
+            self.overlaps.keys().cloned().collect()
+        } else {
+            users.iter().cloned().collect()
+        };
+
+        let num_items = self.item_users.len() as u64;
+
+        for user_a in users.iter() {
+            let overlaps = match self.overlaps.get(user_a) {
+                Some(overlaps) => overlaps,
+                None => continue,
+            };
+            let n_a = self.histories[user_a].len() as u64;
+
+            for (user_b, overlap) in overlaps.iter() {
+                let n_b = self.histories[user_b].len() as u64;
+                let overlap = *overlap as u64;
+
+                let similarity_ab = self.similarity.score(overlap, n_a, n_b, num_items) as f32;
+                let similarity_ba = self.similarity.score(overlap, n_b, n_a, num_items) as f32;
+
+                self.s.entry(*user_a).or_default().insert(*user_b, similarity_ab);
+                self.s.entry(*user_b).or_default().insert(*user_a, similarity_ba);
+            }
+        }
+    }
+}
+
+/// Removes an entry from a nested map, and the inner map once it is empty
+fn remove_entry<V>(map: &mut FnvHashMap<u32, FnvHashMap<u32, V>>, key: u32, inner_key: u32) {
+    if let Some(inner_map) = map.get_mut(&key) {
+        inner_map.remove(&inner_key);
+        if inner_map.is_empty() {
+            map.remove(&key);
+        }
+    }
+}
+
+impl IncrementalDecrementalModel<(u32, Vec<u32>), u32, FnvHashSet<u32>> for UserbasedCF {
+
+    /// Adds the interactions of users with items, repeated interactions are only counted once
+    fn partial_fit(&mut self, interactions: &[(u32, Vec<u32>)]) {
+        let mut users_to_rescore = FnvHashSet::default();
+        let mut num_items_changed = false;
+
+        for (user, items) in interactions.iter() {
+            for item in items.iter() {
+                let history = self.histories.entry(*user).or_default();
+                if history.insert(*item) {
+                    num_items_changed |= self.add_interaction(*user, *item);
+                    users_to_rescore.insert(*user);
+                }
+            }
+        }
+
+        self.rescore(&users_to_rescore, num_items_changed);
+    }
+
+    /// Forgets the given interactions of a user, a user without any remaining interactions is
+    /// removed from the model. Unknown users and interactions are ignored.
+    fn forget(&mut self, interactions: &(u32, Vec<u32>)) {
+        let (user, items) = interactions;
+
+        let history = match self.histories.get_mut(user) {
+            Some(history) => history,
+            None => return,
+        };
+        let forgotten_items: Vec<u32> = items.iter()
+            .filter(|item| history.remove(item))
+            .cloned()
+            .collect();
+
+        let mut num_items_changed = false;
+        for item in forgotten_items {
+            num_items_changed |= self.remove_interaction(*user, item);
+        }
+
+        if self.histories[user].is_empty() {
+            self.histories.remove(user);
+            debug_assert!(!self.overlaps.contains_key(user) && !self.s.contains_key(user));
+        }
+
+        let users_to_rescore: FnvHashSet<u32> = self.histories.get(user).map(|_| *user)
+            .into_iter()
+            .collect();
+        self.rescore(&users_to_rescore, num_items_changed);
+    }
+
+    fn predict(&self, user: &u32) -> FnvHashSet<u32> {
+        self.top_k_neighbours(*user)
+            .into_iter()
+            .map(|scored_user| scored_user.item)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::StdRng;
+    use rand::seq::SliceRandom;
+
+    use crate::IncrementalDecrementalModel;
+    use crate::similarity::Similarity;
+    use crate::userbased::UserbasedCF;
+
+    fn assert_same_model(userbased_cf: &UserbasedCF, retrained_cf: &UserbasedCF) {
+        assert_eq!(userbased_cf.histories, retrained_cf.histories);
+        assert_eq!(userbased_cf.item_users, retrained_cf.item_users);
+        assert_eq!(userbased_cf.overlaps, retrained_cf.overlaps);
+        assert_eq!(userbased_cf.s, retrained_cf.s);
+    }
+
+    #[test]
+    fn toy_example() {
+        let mut userbased_cf = UserbasedCF::new(2);
+        userbased_cf.partial_fit(&[(0, vec![0, 1, 2]), (1, vec![0, 2]), (2, vec![2, 3])]);
+
+        assert_eq!(userbased_cf.s[&0][&1], 2.0 / 3.0);
+        assert_eq!(userbased_cf.s[&0][&2], 1.0 / 4.0);
+        assert_eq!(userbased_cf.recommend(1, 2), vec![(1, 2.0 / 3.0), (3, 1.0 / 3.0)]);
+
+        userbased_cf.forget_user(1);
+
+        assert!(userbased_cf.predict(&1).is_empty());
+        assert!(userbased_cf.s.values().all(|similarities| !similarities.contains_key(&1)));
+        assert!(userbased_cf.overlaps.values().all(|overlaps| !overlaps.contains_key(&1)));
+        assert_eq!(userbased_cf.recommend(2, 2), vec![(0, 0.25), (1, 0.25)]);
+
+        // Unknown users and interactions are ignored
+        userbased_cf.forget(&(1, vec![0, 2]));
+        userbased_cf.forget(&(2, vec![0]));
+
+        let mut retrained_cf = UserbasedCF::new(2);
+        retrained_cf.partial_fit(&[(0, vec![0, 1, 2]), (2, vec![2, 3])]);
+        assert_same_model(&userbased_cf, &retrained_cf);
+    }
+
+    #[test]
+    fn forgetting_matches_retraining() {
+        let similarities = [Similarity::Jaccard, Similarity::ConditionalProbability,
+            Similarity::LogLikelihoodRatio];
+
+        let num_users = 10;
+        let num_items = 8;
+
+        for similarity in similarities.iter() {
+            for seed in 0..10 {
+                let mut rng = StdRng::seed_from_u64(seed);
+
+                let mut histories: Vec<(u32, Vec<u32>)> = (0..num_users)
+                    .map(|user| {
+                        let mut items: Vec<u32> = (0..num_items).collect();
+                        items.shuffle(&mut rng);
+                        items.truncate(rng.gen_range(1, num_items as usize + 1));
+                        (user, items)
+                    })
+                    .collect();
+
+                let mut userbased_cf = UserbasedCF::with_similarity(3, *similarity);
+                userbased_cf.partial_fit(&histories);
+
+                // Forget single interactions of some users and all interactions of others
+                for _ in 0..5 {
+                    let index = rng.gen_range(0, histories.len());
+                    let (user, items) = &mut histories[index];
+                    if items.is_empty() {
+                        continue;
+                    }
+                    let forgotten_items: Vec<u32> = if rng.gen() {
+                        std::mem::take(items)
+                    } else {
+                        items.pop().into_iter().collect()
+                    };
+                    userbased_cf.forget(&(*user, forgotten_items));
+                }
+
+                let mut retrained_cf = UserbasedCF::with_similarity(3, *similarity);
+                retrained_cf.partial_fit(&histories);
+
+                assert_same_model(&userbased_cf, &retrained_cf);
+            }
+        }
+    }
+}
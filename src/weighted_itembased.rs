@@ -0,0 +1,343 @@
+use fnv::{FnvHashMap, FnvHashSet};
+
+use crate::IncrementalDecrementalModel;
+use crate::itembased::top_k;
+
+/// Similarity measures on weighted interaction vectors, e.g., explicit ratings or implicit
+/// confidence values
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WeightedSimilarity {
+    /// Cosine similarity of the weight vectors of the items
+    Cosine,
+    /// Cosine similarity of the weight vectors after subtracting each user's mean weight, which
+    /// removes the bias of users that rate everything high or low
+    AdjustedCosine,
+}
+
+/// Item-based collaborative filtering on weighted interactions. We maintain the dot products and
+/// squared norms of the (centered) item weight vectors, as well as the number of users which
+/// interacted with each item pair. Whenever the weights of a user change, we subtract their old
+/// contribution and add the new one, so ratings can be deleted or changed at any time.
+#[derive(Debug)]
+pub struct WeightedItembasedCF {
+    k: usize,
+    similarity: WeightedSimilarity,
+    c: Vec<FnvHashMap<u32, u32>>,
+    dots: Vec<FnvHashMap<u32, f64>>,
+    s: Vec<FnvHashMap<u32, f32>>,
+    n: Vec<u32>,
+    squared_norms: Vec<f64>,
+    user_weights: FnvHashMap<u32, FnvHashMap<u32, f64>>,
+}
+
+impl WeightedItembasedCF {
+
+    pub fn new(num_items: usize, k: usize, similarity: WeightedSimilarity) -> Self {
+        WeightedItembasedCF {
+            k,
+            similarity,
+            c: vec![FnvHashMap::with_capacity_and_hasher(0, Default::default()); num_items],
+            dots: vec![FnvHashMap::with_capacity_and_hasher(0, Default::default()); num_items],
+            s: vec![FnvHashMap::with_capacity_and_hasher(0, Default::default()); num_items],
+            n: vec![0; num_items],
+            squared_norms: vec![0.0; num_items],
+            user_weights: FnvHashMap::with_capacity_and_hasher(0, Default::default()),
+        }
+    }
+
+    /// Adds `(user, item, weight)` interactions, the weight of an existing interaction is replaced
+    pub fn partial_fit_weights(&mut self, interactions: &[(u32, u32, f64)]) {
+        let mut changes_per_user: FnvHashMap<u32, Vec<(u32, Option<f64>)>> = FnvHashMap::default();
+        for (user, item, weight) in interactions.iter() {
+            changes_per_user.entry(*user).or_default().push((*item, Some(*weight)));
+        }
+
+        let mut users: Vec<u32> = changes_per_user.keys().cloned().collect();
+        users.sort();
+
+        for user in users {
+            self.update_user(user, &changes_per_user[&user]);
+        }
+    }
+
+    /// Forgets the interaction of a user with an item, unknown interactions are ignored
+    pub fn forget_weight(&mut self, user: u32, item: u32) {
+        self.update_user(user, &[(item, None)]);
+    }
+
+    /// Forgets all interactions of a user
+    pub fn forget_user(&mut self, user: u32) {
+        if let Some(weights) = self.user_weights.get(&user) {
+            let changes: Vec<(u32, Option<f64>)> = weights.keys()
+                .map(|item| (*item, None))
+                .collect();
+            self.update_user(user, &changes);
+        }
+    }
+
+    /// Removes the contribution of the user's current weights and adds the contribution of the
+    /// weights after applying the changes, `None` deletes an interaction
+    fn update_user(&mut self, user: u32, changes: &[(u32, Option<f64>)]) {
+        let old_weights = self.user_weights.remove(&user).unwrap_or_default();
+
+        let mut new_weights = old_weights.clone();
+        for (item, change) in changes.iter() {
+            match change {
+                Some(weight) => {
+                    self.ensure_item(*item);
+                    new_weights.insert(*item, *weight);
+                },
+                // Unknown interactions are ignored
+                None => { new_weights.remove(item); },
+            }
+        }
+
+        let old_contributions = self.contributions(&old_weights);
+        let new_contributions = self.contributions(&new_weights);
+
+        let mut items: Vec<u32> = old_weights.keys().chain(new_weights.keys()).cloned().collect();
+        items.sort();
+        items.dedup();
+
+        for (index, item_a) in items.iter().enumerate() {
+            let old_a = old_contributions.get(item_a);
+            let new_a = new_contributions.get(item_a);
+
+            let a = *item_a as usize;
+            match (old_a, new_a) {
+                (Some(_), None) => self.n[a] -= 1,
+                (None, Some(_)) => self.n[a] += 1,
+                _ => {}
+            }
+
+            if let Some(weight) = old_a {
+                self.squared_norms[a] -= weight * weight;
+            }
+            if let Some(weight) = new_a {
+                self.squared_norms[a] += weight * weight;
+            }
+            // Avoid leftover rounding errors for items without any interactions
+            if self.n[a] == 0 {
+                self.squared_norms[a] = 0.0;
+            }
+
+            for item_b in items[index + 1..].iter() {
+                let old_b = old_contributions.get(item_b);
+                let new_b = new_contributions.get(item_b);
+                self.update_pair(*item_a, *item_b, old_a.zip(old_b), new_a.zip(new_b));
+            }
+        }
+
+        if !new_weights.is_empty() {
+            self.user_weights.insert(user, new_weights);
+        }
+
+        self.rescore(&items);
+    }
+
+    fn update_pair(
+        &mut self,
+        item_a: u32,
+        item_b: u32,
+        old_weights: Option<(&f64, &f64)>,
+        new_weights: Option<(&f64, &f64)>
+    ) {
+        let (a, b) = (item_a as usize, item_b as usize);
+
+        if let Some((weight_a, weight_b)) = old_weights {
+            *self.c[a].get_mut(&item_b).unwrap() -= 1;
+            *self.c[b].get_mut(&item_a).unwrap() -= 1;
+            *self.dots[a].get_mut(&item_b).unwrap() -= weight_a * weight_b;
+            *self.dots[b].get_mut(&item_a).unwrap() -= weight_a * weight_b;
+        }
+
+        if let Some((weight_a, weight_b)) = new_weights {
+            *self.c[a].entry(item_b).or_insert(0) += 1;
+            *self.c[b].entry(item_a).or_insert(0) += 1;
+            *self.dots[a].entry(item_b).or_insert(0.0) += weight_a * weight_b;
+            *self.dots[b].entry(item_a).or_insert(0.0) += weight_a * weight_b;
+        }
+
+        // Drop pairs which are not rated together anymore
+        if old_weights.is_some() && self.c[a][&item_b] == 0 {
+            for (item, other_item) in &[(a, item_b), (b, item_a)] {
+                self.c[*item].remove(other_item);
+                self.dots[*item].remove(other_item);
+                self.s[*item].remove(other_item);
+            }
+        }
+    }
+
+    /// The weights with which the interactions of a user contribute to the dot products
+    fn contributions(&self, weights: &FnvHashMap<u32, f64>) -> FnvHashMap<u32, f64> {
+        match self.similarity {
+            WeightedSimilarity::Cosine => weights.clone(),
+            WeightedSimilarity::AdjustedCosine => {
+                if weights.is_empty() {
+                    return FnvHashMap::default();
+                }
+
+                // Sum in item order, so that the mean does not depend on the order of the map
+                let mut items: Vec<&u32> = weights.keys().collect();
+                items.sort();
+                let sum: f64 = items.iter().map(|item| weights[*item]).sum();
+                let mean = sum / weights.len() as f64;
+
+                weights.iter().map(|(item, weight)| (*item, weight - mean)).collect()
+            }
+        }
+    }
+
+    fn rescore(&mut self, items: &[u32]) {
+        for item_a in items.iter() {
+            let norm_a = self.squared_norms[*item_a as usize];
+            for (item_b, dot) in self.dots[*item_a as usize].iter() {
+                let norm_b = self.squared_norms[*item_b as usize];
+                let denominator = (norm_a * norm_b).sqrt();
+
+                // Centered weights can be all zero, we consider such items dissimilar
+                let similarity = if denominator > 0.0 { (dot / denominator) as f32 } else { 0.0 };
+
+                self.s[*item_a as usize].insert(*item_b, similarity);
+                self.s[*item_b as usize].insert(*item_a, similarity);
+            }
+        }
+    }
+
+    /// Similarities of all pairs rated together with item_a > item_b, sorted by pair, to compare
+    /// the model with the differential implementation
+    #[cfg(test)]
+    pub(crate) fn similarities(&self) -> Vec<((u32, u32), f32)> {
+        let mut similarities: Vec<((u32, u32), f32)> = self.s.iter().enumerate()
+            .flat_map(|(item_a, similarities)| {
+                similarities.iter()
+                    .filter(move |(item_b, _similarity)| (item_a as u32) > **item_b)
+                    .map(move |(item_b, similarity)| ((item_a as u32, *item_b), *similarity))
+            })
+            .collect();
+
+        similarities.sort_by_key(|(item_pair, _similarity)| *item_pair);
+        similarities
+    }
+
+    fn ensure_item(&mut self, item: u32) {
+        let num_items = item as usize + 1;
+        if num_items > self.n.len() {
+            self.c.resize(num_items, FnvHashMap::with_capacity_and_hasher(0, Default::default()));
+            self.dots.resize(num_items, FnvHashMap::with_capacity_and_hasher(0, Default::default()));
+            self.s.resize(num_items, FnvHashMap::with_capacity_and_hasher(0, Default::default()));
+            self.n.resize(num_items, 0);
+            self.squared_norms.resize(num_items, 0.0);
+        }
+    }
+}
+
+impl IncrementalDecrementalModel<(u32, u32, f64), u32, FnvHashSet<u32>> for WeightedItembasedCF {
+
+    fn partial_fit(&mut self, interactions: &[(u32, u32, f64)]) {
+        self.partial_fit_weights(interactions);
+    }
+
+    /// Forgets an interaction, which must have the same weight as the stored one. Unknown
+    /// interactions are ignored.
+    fn forget(&mut self, interaction: &(u32, u32, f64)) {
+        let (user, item, weight) = interaction;
+        let stored_weight = self.user_weights.get(user).and_then(|weights| weights.get(item));
+        if let Some(stored_weight) = stored_weight {
+            assert_eq!(stored_weight, weight, "The weight differs from the stored interaction");
+            self.forget_weight(*user, *item);
+        }
+    }
+
+    fn predict(&self, item: &u32) -> FnvHashSet<u32> {
+        match self.s.get(*item as usize) {
+            Some(similarities) => {
+                top_k(similarities, self.k)
+                    .into_iter()
+                    .map(|scored_item| scored_item.item)
+                    .collect()
+            },
+            None => FnvHashSet::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::IncrementalDecrementalModel;
+    use crate::weighted_itembased::{WeightedItembasedCF, WeightedSimilarity};
+
+    fn assert_close(model: &WeightedItembasedCF, retrained: &WeightedItembasedCF) {
+        assert_eq!(model.c, retrained.c);
+        assert_eq!(model.n, retrained.n);
+
+        for (similarities, retrained_similarities) in model.s.iter().zip(retrained.s.iter()) {
+            assert_eq!(similarities.len(), retrained_similarities.len());
+            for (item, similarity) in similarities.iter() {
+                assert!((similarity - retrained_similarities[item]).abs() < 0.0001);
+            }
+        }
+    }
+
+    #[test]
+    fn cosine_on_ratings() {
+        let mut model = WeightedItembasedCF::new(3, 2, WeightedSimilarity::Cosine);
+        model.partial_fit(&[(0, 0, 1.0), (0, 1, 2.0), (1, 0, 2.0), (1, 1, 4.0), (1, 2, 1.0)]);
+
+        // Item 0 and 1 have parallel rating vectors
+        assert!((model.s[0][&1] - 1.0).abs() < 0.0001);
+        assert!(model.s[0][&2] < model.s[0][&1]);
+    }
+
+    #[test]
+    fn changing_and_deleting_ratings_matches_retraining() {
+        for similarity in &[WeightedSimilarity::Cosine, WeightedSimilarity::AdjustedCosine] {
+            let mut model = WeightedItembasedCF::new(4, 2, *similarity);
+            model.partial_fit(&[
+                (0, 0, 5.0), (0, 1, 3.0), (0, 2, 4.0),
+                (1, 0, 3.0), (1, 1, 1.0), (1, 3, 2.0),
+                (2, 1, 4.0), (2, 2, 5.0), (2, 3, 1.0),
+            ]);
+
+            // Change a rating, delete a rating and forget a user
+            model.partial_fit(&[(0, 2, 1.0)]);
+            model.forget(&(1, 3, 2.0));
+            model.forget_user(2);
+
+            let mut retrained = WeightedItembasedCF::new(4, 2, *similarity);
+            retrained.partial_fit(&[
+                (0, 0, 5.0), (0, 1, 3.0), (0, 2, 1.0),
+                (1, 0, 3.0), (1, 1, 1.0),
+            ]);
+
+            assert_close(&model, &retrained);
+            assert!(model.dots[3].is_empty());
+            assert_eq!(model.squared_norms[3], 0.0);
+        }
+    }
+
+    #[test]
+    fn unknown_interactions_are_ignored() {
+        let mut model = WeightedItembasedCF::new(2, 2, WeightedSimilarity::Cosine);
+        model.partial_fit(&[(0, 0, 4.0), (0, 1, 2.0), (1, 1, 3.0)]);
+
+        model.forget_weight(0, 7);
+        model.forget_weight(5, 0);
+        model.forget(&(1, 0, 1.0));
+
+        let mut retrained = WeightedItembasedCF::new(2, 2, WeightedSimilarity::Cosine);
+        retrained.partial_fit(&[(0, 0, 4.0), (0, 1, 2.0), (1, 1, 3.0)]);
+
+        assert_close(&model, &retrained);
+        assert_eq!(model.n.len(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "The weight differs from the stored interaction")]
+    fn forgetting_with_a_different_weight_fails() {
+        let mut model = WeightedItembasedCF::new(2, 2, WeightedSimilarity::Cosine);
+        model.partial_fit(&[(0, 0, 4.0), (0, 1, 2.0)]);
+        model.forget(&(0, 1, 3.0));
+    }
+}